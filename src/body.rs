@@ -2,20 +2,37 @@ use obj;
 use mesh;
 use texture;
 use na;
+use na::Norm;
 use ode;
 use math::*;
 use errors::*;
+use material::Material;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std;
 
+#[allow(dead_code)]
 pub enum BodyShape {
     Sphere { radius: f32 },
+    // full side lengths
+    Box { size: Vec3 },
+    // capsules and cylinders lie along the local z axis; a capsule's length excludes its caps
+    Capsule { radius: f32, length: f32 },
+    Cylinder { radius: f32, length: f32 },
     TriangleSoup {
         vertices: Vec<f64>,
         indices: Vec<u32>,
     },
+    // planes are (a, b, c, d) with outward normals; a polygon is its vertex count followed by
+    // the vertex indices. ODE points to these instead of copying them.
+    ConvexHull {
+        planes: Vec<f64>,
+        points: Vec<f64>,
+        polygons: Vec<u32>,
+    },
+    // parts placed relative to the body
+    Compound { parts: Vec<(Iso3, BodyShape)> },
     HeightField,
 }
 
@@ -28,6 +45,90 @@ impl BodyShape {
         BodyShape::from_vertices(positions)
     }
 
+    // The faces of an .obj that is already convex, such as a gem
+    pub fn convex_from_obj<P: AsRef<Path> + ?Sized>(path: &P) -> Result<BodyShape> {
+        let (positions, _normals, _texcoord) =
+            obj::load_obj(path).chain_err(|| "unable to load .obj")?;
+
+        BodyShape::convex_from_vertices(positions)
+    }
+
+    // Three vertices per triangle of a convex mesh; the triangles may face either way, but a
+    // vertex outside of any of them makes an error
+    pub fn convex_from_vertices(positions: Vec<Pnt3>) -> Result<BodyShape> {
+        // the obj loader gives three vertices per triangle; share the equal ones
+        let mut unique: Vec<Pnt3> = Vec::new();
+        let mut tris = Vec::with_capacity(positions.len());
+        for p in positions.iter() {
+            let i = match unique.iter().position(|u| u == p) {
+                Some(i) => i,
+                None => {
+                    unique.push(*p);
+                    unique.len() - 1
+                }
+            };
+            tris.push(i as u32);
+        }
+
+        let mut center = Vec3::new(0.0, 0.0, 0.0);
+        for p in unique.iter() {
+            center = center + p.to_vector();
+        }
+        let center = center / unique.len().max(1) as f32;
+
+        let mut planes = Vec::new();
+        let mut polygons = Vec::new();
+        for tri in tris.chunks(3) {
+            let (a, b, c) = (unique[tri[0] as usize].to_vector(),
+                             unique[tri[1] as usize].to_vector(),
+                             unique[tri[2] as usize].to_vector());
+            let normal = na::cross(&(b - a), &(c - a));
+            if normal.norm() < 1.0e-6 {
+                continue; // degenerate
+            }
+            let mut normal = normal.normalize();
+            let mut order = [tri[0], tri[1], tri[2]];
+            if na::dot(&normal, &(center - a)) > 0.0 {
+                // wound inside out
+                normal = -normal;
+                order = [tri[0], tri[2], tri[1]];
+            }
+            planes.extend(&[normal.x as f64,
+                            normal.y as f64,
+                            normal.z as f64,
+                            na::dot(&normal, &a) as f64]);
+            polygons.push(3);
+            polygons.extend(&order);
+        }
+        if planes.is_empty() {
+            bail!("no faces for a convex hull");
+        }
+
+        // ODE trusts the planes to bound the shape, which only holds if it's convex
+        let size = unique.iter().map(|p| (p.to_vector() - center).norm()).fold(0.0, f32::max);
+        for plane in planes.chunks(4) {
+            let normal = Vec3::new(plane[0] as f32, plane[1] as f32, plane[2] as f32);
+            for p in unique.iter() {
+                if na::dot(&normal, &p.to_vector()) - plane[3] as f32 > size * 1.0e-4 {
+                    bail!("mesh is not convex");
+                }
+            }
+        }
+
+        let mut points = Vec::with_capacity(unique.len() * 3);
+        for p in unique.iter() {
+            points.push(p.x as f64);
+            points.push(p.y as f64);
+            points.push(p.z as f64);
+        }
+
+        Ok(BodyShape::ConvexHull {
+            planes: planes,
+            points: points,
+            polygons: polygons,
+        })
+    }
+
     pub fn from_vertices(positions: Vec<Pnt3>) -> Result<BodyShape> {
         let mut vertices = Vec::with_capacity(positions.len() * 3);
         for v in positions.iter() {
@@ -43,6 +144,18 @@ impl BodyShape {
     }
 }
 
+// the shapes that got a geom each, in the order that World made the geoms of a body
+pub fn leaf_shapes<'a>(shape: &'a BodyShape, out: &mut Vec<&'a BodyShape>) {
+    match *shape {
+        BodyShape::Compound { ref parts } => {
+            for &(_, ref part) in parts.iter() {
+                leaf_shapes(part, out);
+            }
+        }
+        _ => out.push(shape),
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BodyConfig {
     pub fixed: bool,
@@ -52,6 +165,14 @@ pub struct BodyConfig {
     pub category_bits: u64,
     pub collide_bits: u64,
     pub collide_sound: Option<usize>,
+    pub material: Material,
+    // computed from the shape and density if not given; the inertia is the diagonal of the
+    // inertia tensor around the center of mass
+    pub mass: Option<f32>,
+    pub inertia: Option<Vec3>,
+    // a sensor reports overlaps with the bodies in its collide_bits categories instead of
+    // colliding with them
+    pub sensor: bool,
 }
 
 // individual bits for where object belongs to
@@ -66,7 +187,7 @@ pub const BODY_CATEGORY_ALL_BIT: u64 = 0xffffffff;
 pub const BODY_COLLIDE_PLAYER: u64 = BODY_CATEGORY_ALL_BIT;
 pub const BODY_COLLIDE_OBJS: u64 = BODY_CATEGORY_ALL_BIT & !BODY_CATEGORY_GEAR_BIT;
 pub const BODY_COLLIDE_TERRAIN: u64 = BODY_CATEGORY_PLAYER_BIT | BODY_CATEGORY_OBJS_BIT;
-pub const _BODY_COLLIDE_GEAR: u64 = BODY_CATEGORY_PLAYER_BIT;
+pub const BODY_COLLIDE_GEAR: u64 = BODY_CATEGORY_PLAYER_BIT;
 
 impl Default for BodyConfig {
     fn default() -> Self {
@@ -78,38 +199,167 @@ impl Default for BodyConfig {
             category_bits: BODY_CATEGORY_OBJS_BIT,
             collide_bits: BODY_COLLIDE_OBJS,
             collide_sound: None,
+            material: Material::Default,
+            mass: None,
+            inertia: None,
+            sensor: false,
+        }
+    }
+}
+
+// Names a body in a World. A deleted body's slot gets reused with the next generation, so an old
+// handle can never refer to the new body.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BodyHandle {
+    pub index: u32,
+    pub generation: u32,
+}
+
+// The whole handle goes in an ODE user data pointer, so that needs 64 bits; the generation that
+// catches stale handles would be cut off in a smaller one.
+#[cfg(not(target_pointer_width = "64"))]
+compile_error!("body handles are stored in ODE user data pointers, which must be 64 bits wide");
+
+impl BodyHandle {
+    // ODE keeps this in the body user data pointer
+    pub fn to_user_data(&self) -> *mut std::os::raw::c_void {
+        (((self.generation as u64) << 32) | self.index as u64) as usize as *mut std::os::raw::c_void
+    }
+
+    pub fn from_user_data(data: *mut std::os::raw::c_void) -> BodyHandle {
+        let data = data as usize as u64;
+        BodyHandle {
+            index: data as u32,
+            generation: (data >> 32) as u32,
         }
     }
 }
 
+// a model matrix from a position and ODE's 3x4 rotation matrix
+unsafe fn posrot_homogeneous(pos: Vec3, rot: *const ode::dReal) -> na::Matrix4<f32> {
+    na::Matrix4::new(*rot.offset(0) as f32,
+                     *rot.offset(1) as f32,
+                     *rot.offset(2) as f32,
+                     pos.x,
+                     *rot.offset(4) as f32,
+                     *rot.offset(5) as f32,
+                     *rot.offset(6) as f32,
+                     pos.y,
+                     *rot.offset(8) as f32,
+                     *rot.offset(9) as f32,
+                     *rot.offset(10) as f32,
+                     pos.z,
+                     0.0,
+                     0.0,
+                     0.0,
+                     1.0)
+}
+
+// Geometry data that ODE keeps next to a geom and that has to be destroyed separately
+pub enum OdeGeomData {
+    TriMesh(ode::dTriMeshDataID),
+    HeightField(ode::dHeightfieldDataID),
+}
+
+// Owns its ODE body and geoms, which are destroyed when the body is dropped or along with the
+// World it was created in, whichever comes first. A body that outlives its world is empty.
 pub struct Body {
     pub mesh: Option<Rc<RefCell<mesh::Mesh>>>,
     pub texture: Option<Rc<texture::Texture>>,
     pub config: BodyConfig,
-    pub shape: Rc<BodyShape>, // NOTE: holds memory of TriMesh!
+    pub shape: Rc<BodyShape>, // NOTE: holds memory of TriMesh, ode_data points to it!
     pub ode_body: ode::dBodyID,
-    pub ode_geom: ode::dGeomID,
-    pub id: u64,
+    pub ode_geoms: Vec<ode::dGeomID>, // one per part of a compound shape
+    pub ode_data: Vec<OdeGeomData>,
+    // in the shape's frame; the ODE body sits here but positions are given for the shape origin
+    pub center_of_mass: Vec3,
+    pub handle: BodyHandle,
     pub collide_sound: Option<usize>, // used for collectable diamonds and powerups
     pub shaded: bool,
+    // shape origin and orientation before the last physics tick, for drawing between ticks
+    pub previous: Option<(Vec3, [f64; 4])>,
+    // of the shape of each geom, for buoyancy; empty for fixed bodies
+    pub geom_volumes: Vec<f32>,
+    // cleared when the world is destroyed, taking the ODE body and its joints along
+    pub world_alive: Rc<Cell<bool>>,
+}
+
+// Full dynamic state of one body, kept in ODE's own precision so that restoring it is exact
+#[derive(Debug, Clone)]
+pub struct BodySnapshot {
+    pub handle: BodyHandle,
+    pub position: [f64; 3],
+    pub quaternion: [f64; 4],
+    pub linear_velocity: [f64; 3],
+    pub angular_velocity: [f64; 3],
 }
 
 impl std::cmp::PartialEq for Body {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        self.handle == other.handle
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        self.destroy_ode();
+        unsafe {
+            for data in self.ode_data.iter() {
+                match *data {
+                    OdeGeomData::TriMesh(data) => ode::dGeomTriMeshDataDestroy(data),
+                    OdeGeomData::HeightField(data) => ode::dGeomHeightfieldDataDestroy(data),
+                }
+            }
+        }
+        // only after this does self.shape let go of the trimesh vertices that ODE was using
     }
 }
 
 impl Body {
-    pub fn get_position(&self) -> Vec3 {
+    // Destroys the geoms and the ODE body, or only the geoms if the world already took the body
+    // with it. The world does this to the bodies that someone else still holds when it goes;
+    // such a body stays around without any ODE objects and must not be used anymore.
+    pub fn destroy_ode(&mut self) {
+        unsafe {
+            // also removes the geoms from their space if they're still there
+            for &geom in self.ode_geoms.iter() {
+                ode::dGeomDestroy(geom);
+            }
+            if !self.ode_body.is_null() && self.world_alive.get() {
+                ode::dBodyDestroy(self.ode_body);
+            }
+        }
+        self.ode_geoms.clear();
+        self.ode_body = std::ptr::null_mut();
+    }
+
+    // the center of mass offset rotated to world coordinates
+    fn world_center_of_mass(&self) -> Vec3 {
+        let c = self.center_of_mass;
+        if c == na::zero() {
+            return c;
+        }
         unsafe {
+            let r = ode::dBodyGetRotation(self.ode_body);
+            Vec3::new((*r.offset(0) * c.x as f64 + *r.offset(1) * c.y as f64 +
+                       *r.offset(2) * c.z as f64) as f32,
+                      (*r.offset(4) * c.x as f64 + *r.offset(5) * c.y as f64 +
+                       *r.offset(6) * c.z as f64) as f32,
+                      (*r.offset(8) * c.x as f64 + *r.offset(9) * c.y as f64 +
+                       *r.offset(10) * c.z as f64) as f32)
+        }
+    }
+    pub fn get_position(&self) -> Vec3 {
+        let v = unsafe {
             let v = ode::dBodyGetPosition(self.ode_body);
             Vec3::new(*v.offset(0) as f32,
                       *v.offset(1) as f32,
                       *v.offset(2) as f32)
-        }
+        };
+        v - self.world_center_of_mass()
     }
     pub fn set_position(&mut self, pos: Vec3) {
+        let pos = pos + self.world_center_of_mass();
         unsafe {
             ode::dBodySetPosition(self.ode_body, pos.x as f64, pos.y as f64, pos.z as f64);
         }
@@ -127,6 +377,11 @@ impl Body {
             ode::dBodySetLinearVel(self.ode_body, vel.x as f64, vel.y as f64, vel.z as f64);
         }
     }
+    pub fn set_angular_velocity(&mut self, vel: Vec3) {
+        unsafe {
+            ode::dBodySetAngularVel(self.ode_body, vel.x as f64, vel.y as f64, vel.z as f64);
+        }
+    }
     pub fn add_torque(&mut self, torque: Vec3) {
         unsafe {
             ode::dBodyAddTorque(self.ode_body,
@@ -143,33 +398,100 @@ impl Body {
                                force.z as f64)
         }
     }
-    // 0  1  2  3
-    // 4  5  6  7
-    // 8  9  10 11
-    // 12 13 14 15
-    pub fn get_posrot_homogeneous(&mut self) -> na::Matrix4<f32> {
+    // where add_force pushes
+    pub fn get_center_of_mass_position(&self) -> Vec3 {
+        self.get_position() + self.world_center_of_mass()
+    }
+    pub fn get_mass(&self) -> f32 {
+        unsafe {
+            let mut mass: ode::dMass = std::mem::zeroed();
+            ode::dBodyGetMass(self.ode_body, &mut mass);
+            mass.mass as f32
+        }
+    }
+    pub fn is_kinematic(&self) -> bool {
+        unsafe { ode::dBodyIsKinematic(self.ode_body) != 0 }
+    }
+    fn get_quaternion(&self) -> [f64; 4] {
+        unsafe {
+            let q = ode::dBodyGetQuaternion(self.ode_body);
+            [*q.offset(0), *q.offset(1), *q.offset(2), *q.offset(3)]
+        }
+    }
+    // Remember where the body is now as its state before the next physics tick
+    pub fn save_previous(&mut self) {
+        self.previous = Some((self.get_position(), self.get_quaternion()));
+    }
+
+    // alpha of the way from the previous tick to the current state
+    fn interpolated(&self, alpha: f32) -> (Vec3, [f64; 4]) {
+        let (pos, q) = (self.get_position(), self.get_quaternion());
+        let (prev_pos, prev_q) = match self.previous {
+            Some(prev) => prev,
+            None => return (pos, q),
+        };
+
+        // q and -q are the same orientation; go the shorter way around
+        let dot = (0..4).map(|i| prev_q[i] * q[i]).sum::<f64>();
+        let sign = if dot < 0.0 { -1.0 } else { 1.0 };
+        let alpha64 = alpha as f64;
+        let mut mixed = [0.0; 4];
+        for i in 0..4 {
+            mixed[i] = prev_q[i] + (sign * q[i] - prev_q[i]) * alpha64;
+        }
+        let len = mixed.iter().map(|x| x * x).sum::<f64>().sqrt();
+        for x in mixed.iter_mut() {
+            *x /= len;
+        }
+
+        (prev_pos + (pos - prev_pos) * alpha, mixed)
+    }
+
+    pub fn get_interpolated_position(&self, alpha: f32) -> Vec3 {
+        self.interpolated(alpha).0
+    }
+
+    pub fn get_interpolated_posrot_homogeneous(&self, alpha: f32) -> na::Matrix4<f32> {
+        let (pos, q) = self.interpolated(alpha);
+        let mut rot = [0.0; 12];
         unsafe {
-            let pos = ode::dBodyGetPosition(self.ode_body);
-            let rot = ode::dBodyGetRotation(self.ode_body);
-            na::Matrix4::new(*rot.offset(0) as f32,
-                             *rot.offset(1) as f32,
-                             *rot.offset(2) as f32,
-                             *pos.offset(0) as f32,
-                             *rot.offset(4) as f32,
-                             *rot.offset(5) as f32,
-                             *rot.offset(6) as f32,
-                             *pos.offset(1) as f32,
-                             *rot.offset(8) as f32,
-                             *rot.offset(9) as f32,
-                             *rot.offset(10) as f32,
-                             *pos.offset(2) as f32,
-                             0.0,
-                             0.0,
-                             0.0,
-                             1.0)
+            ode::dRfromQ(rot.as_mut_ptr(), q.as_ptr());
+            posrot_homogeneous(pos, rot.as_ptr())
         }
     }
 
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> BodySnapshot {
+        unsafe {
+            let p = ode::dBodyGetPosition(self.ode_body);
+            let q = ode::dBodyGetQuaternion(self.ode_body);
+            let v = ode::dBodyGetLinearVel(self.ode_body);
+            let w = ode::dBodyGetAngularVel(self.ode_body);
+            BodySnapshot {
+                handle: self.handle,
+                position: [*p.offset(0), *p.offset(1), *p.offset(2)],
+                quaternion: [*q.offset(0), *q.offset(1), *q.offset(2), *q.offset(3)],
+                linear_velocity: [*v.offset(0), *v.offset(1), *v.offset(2)],
+                angular_velocity: [*w.offset(0), *w.offset(1), *w.offset(2)],
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn restore(&mut self, snap: &BodySnapshot) {
+        unsafe {
+            let (p, v, w) = (snap.position, snap.linear_velocity, snap.angular_velocity);
+            ode::dBodySetPosition(self.ode_body, p[0], p[1], p[2]);
+            ode::dBodySetQuaternion(self.ode_body, snap.quaternion.as_ptr());
+            ode::dBodySetLinearVel(self.ode_body, v[0], v[1], v[2]);
+            ode::dBodySetAngularVel(self.ode_body, w[0], w[1], w[2]);
+            // a body may have been auto-disabled after the snapshot was taken
+            ode::dBodyEnable(self.ode_body);
+        }
+        // don't draw it sliding over from where it was
+        self.previous = None;
+    }
+
     pub fn set_finite_rotation_mode(&mut self, enabled: bool) {
         // true: allegedly stabler fast speed rotation
         unsafe {
@@ -177,3 +499,58 @@ impl Body {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the corners of a cube from -1 to 1; bit 0 of the index is x, bit 1 y and bit 2 z
+    fn corner(i: u32) -> Pnt3 {
+        let side = |bit: u32| if i & bit != 0 { 1.0 } else { -1.0 };
+        Pnt3::new(side(1), side(2), side(4))
+    }
+
+    // two triangles per quad, given around its edge
+    fn triangles(quads: &[[u32; 4]]) -> Vec<Pnt3> {
+        quads.iter()
+            .flat_map(|q| vec![q[0], q[1], q[2], q[0], q[2], q[3]])
+            .map(corner)
+            .collect()
+    }
+
+    // all but the top
+    const SIDES: [[u32; 4]; 5] = [[0, 2, 6, 4],
+                                  [1, 3, 7, 5],
+                                  [0, 1, 5, 4],
+                                  [0, 1, 3, 2],
+                                  [4, 5, 7, 6]];
+
+    #[test]
+    fn convex_mesh_becomes_a_hull() {
+        let mut quads = SIDES.to_vec();
+        // the top, wound the other way than the rest
+        quads.push([6, 7, 3, 2]);
+        match BodyShape::convex_from_vertices(triangles(&quads)).unwrap() {
+            BodyShape::ConvexHull { planes, points, .. } => {
+                assert_eq!(planes.len(), 12 * 4);
+                assert_eq!(points.len(), 8 * 3);
+                // unit normals, all one away from the middle
+                for plane in planes.chunks(4) {
+                    assert!((plane[3] - 1.0).abs() < 1.0e-6);
+                }
+            }
+            _ => panic!("not a convex hull"),
+        }
+    }
+
+    #[test]
+    fn concave_mesh_is_rejected() {
+        // the top caved in to the middle of the cube
+        let mut positions = triangles(&SIDES);
+        let middle = Pnt3::new(0.0, 0.0, 0.0);
+        for &(a, b) in &[(2, 3), (3, 7), (7, 6), (6, 2)] {
+            positions.extend(&[corner(a), corner(b), middle]);
+        }
+        assert!(BodyShape::convex_from_vertices(positions).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Lines that show what ODE sees: the geoms and their bounding boxes, the contacts, the joints and
+// the heightfield that the terrain collides with. Geoms are coloured by their collision category.
+
+use body::{BodyShape, leaf_shapes, BODY_CATEGORY_PLAYER_BIT, BODY_CATEGORY_OBJS_BIT,
+           BODY_CATEGORY_TERRAIN_BIT, BODY_CATEGORY_GEAR_BIT};
+use errors::*;
+use glium;
+use joint::{Joint, JointKind};
+use math::*;
+use ode;
+use world::{World, ContactPhase};
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+implement_vertex!(Vertex, position, color);
+
+static DEBUG_V: &'static str = r#"
+    #version 140
+
+    uniform mat4 perspective;
+    uniform mat4 modelview;
+
+    in vec3 position;
+    in vec3 color;
+
+    out vec3 f_color;
+
+    void main() {
+        gl_Position = perspective * modelview * vec4(position, 1.0);
+        f_color = color;
+    }
+"#;
+
+static DEBUG_F: &'static str = r#"
+    #version 140
+
+    in vec3 f_color;
+
+    out vec4 color;
+
+    void main() {
+        color = vec4(f_color, 1.0);
+    }
+"#;
+
+// line segments in a circle
+const CIRCLE_SEGMENTS: usize = 16;
+
+const CONTACT_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+const JOINT_COLOR: [f32; 3] = [1.0, 0.0, 1.0];
+
+// by the lowest category bit that is set
+pub fn category_color(bits: u64) -> [f32; 3] {
+    if bits & BODY_CATEGORY_PLAYER_BIT != 0 {
+        [1.0, 1.0, 0.0]
+    } else if bits & BODY_CATEGORY_OBJS_BIT != 0 {
+        [0.3, 0.6, 1.0]
+    } else if bits & BODY_CATEGORY_TERRAIN_BIT != 0 {
+        [0.0, 0.8, 0.0]
+    } else if bits & BODY_CATEGORY_GEAR_BIT != 0 {
+        [1.0, 0.5, 0.0]
+    } else {
+        [0.7, 0.7, 0.7]
+    }
+}
+
+fn dim(color: [f32; 3]) -> [f32; 3] {
+    [color[0] * 0.4, color[1] * 0.4, color[2] * 0.4]
+}
+
+// position and the local x, y and z axes of a geom in world space
+unsafe fn geom_frame(geom: ode::dGeomID) -> (Vec3, Vec3, Vec3, Vec3) {
+    let p = ode::dGeomGetPosition(geom);
+    let r = ode::dGeomGetRotation(geom);
+    // the rotation is a row-major 3x4 matrix, so the columns are the axes
+    let axis = |c: isize| {
+        Vec3::new(*r.offset(c) as f32,
+                  *r.offset(4 + c) as f32,
+                  *r.offset(8 + c) as f32)
+    };
+    (Vec3::new(*p as f32, *p.offset(1) as f32, *p.offset(2) as f32), axis(0), axis(1), axis(2))
+}
+
+fn vec3(v: &ode::dVector3) -> Vec3 {
+    Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32)
+}
+
+pub struct DebugDraw {
+    program: glium::Program,
+    vertices: Vec<Vertex>,
+    pub enabled: bool,
+    // the terrain is big, so its grid is drawn only this far from the focus
+    pub grid_radius: f32,
+}
+
+impl DebugDraw {
+    pub fn new<F: glium::backend::Facade>(facade: &F) -> Result<DebugDraw> {
+        Ok(DebugDraw {
+            program: glium::Program::from_source(facade, DEBUG_V, DEBUG_F, None)
+                .chain_err(|| "failed to load debug draw shader")?,
+            vertices: Vec::new(),
+            enabled: false,
+            grid_radius: 40.0,
+        })
+    }
+
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 3]) {
+        self.vertices.push(Vertex {
+            position: [a.x, a.y, a.z],
+            color: color,
+        });
+        self.vertices.push(Vertex {
+            position: [b.x, b.y, b.z],
+            color: color,
+        });
+    }
+
+    // three short lines crossing at the point
+    pub fn cross(&mut self, p: Vec3, size: f32, color: [f32; 3]) {
+        for &(x, y, z) in &[(size, 0.0, 0.0), (0.0, size, 0.0), (0.0, 0.0, size)] {
+            let d = Vec3::new(x, y, z);
+            self.line(p - d, p + d, color);
+        }
+    }
+
+    // in the plane of the unit vectors u and v
+    fn circle(&mut self, center: Vec3, u: Vec3, v: Vec3, radius: f32, color: [f32; 3]) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    // the edges of a box; corner(i) gives the corner with x, y and z at its max in bits 0, 1, 2
+    fn box_edges<F: Fn(usize) -> Vec3>(&mut self, corner: F, color: [f32; 3]) {
+        for i in 0..8 {
+            for &bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // The geoms and their bounding boxes, the contacts of the last tick, and the terrain around
+    // the focus
+    pub fn add_world(&mut self, world: &World, focus: Vec3) {
+        for body in world.bodies() {
+            let body = body.borrow();
+            let mut shapes = Vec::new();
+            leaf_shapes(&body.shape, &mut shapes);
+            for (&geom, shape) in body.ode_geoms.iter().zip(shapes) {
+                unsafe {
+                    self.add_geom(geom, shape);
+                }
+            }
+        }
+
+        for contact in world.tick_contacts() {
+            if contact.phase == ContactPhase::End {
+                continue;
+            }
+            self.cross(contact.point, 0.2, CONTACT_COLOR);
+            self.line(contact.point, contact.point + contact.normal, CONTACT_COLOR);
+        }
+
+        self.add_heightfield(world, focus);
+    }
+
+    unsafe fn add_geom(&mut self, geom: ode::dGeomID, shape: &BodyShape) {
+        let class = ode::dGeomGetClass(geom);
+        // heightfields have no real bounds, the grid shows them instead
+        if class == ode::dHeightfieldClass as i32 {
+            return;
+        }
+
+        let color = category_color(ode::dGeomGetCategoryBits(geom) as u64);
+        let (pos, ax, ay, az) = geom_frame(geom);
+        let local = |p: Vec3| pos + ax * p.x + ay * p.y + az * p.z;
+
+        if class == ode::dSphereClass as i32 {
+            let r = ode::dGeomSphereGetRadius(geom) as f32;
+            self.circle(pos, ax, ay, r, color);
+            self.circle(pos, ay, az, r, color);
+            self.circle(pos, az, ax, r, color);
+        } else if class == ode::dBoxClass as i32 {
+            let mut size: ode::dVector3 = [0.0; 4];
+            ode::dGeomBoxGetLengths(geom, size.as_mut_ptr());
+            let half = vec3(&size) * 0.5;
+            let corner = |i: usize| {
+                local(Vec3::new(if i & 1 != 0 { half.x } else { -half.x },
+                                if i & 2 != 0 { half.y } else { -half.y },
+                                if i & 4 != 0 { half.z } else { -half.z }))
+            };
+            self.box_edges(corner, color);
+        } else if class == ode::dCapsuleClass as i32 || class == ode::dCylinderClass as i32 {
+            let (mut r, mut len) = (0.0, 0.0);
+            if class == ode::dCapsuleClass as i32 {
+                ode::dGeomCapsuleGetParams(geom, &mut r, &mut len);
+            } else {
+                ode::dGeomCylinderGetParams(geom, &mut r, &mut len);
+            }
+            let (r, half) = (r as f32, len as f32 * 0.5);
+            for &end in &[-half, half] {
+                let c = pos + az * end;
+                self.circle(c, ax, ay, r, color);
+                if class == ode::dCapsuleClass as i32 {
+                    // rough caps
+                    self.circle(c, ax, az, r, color);
+                    self.circle(c, ay, az, r, color);
+                }
+            }
+            for &side in &[ax, -ax, ay, -ay] {
+                self.line(pos + side * r - az * half, pos + side * r + az * half, color);
+            }
+        } else if class == ode::dTriMeshClass as i32 {
+            let (mut v0, mut v1, mut v2): (ode::dVector3, ode::dVector3, ode::dVector3) =
+                ([0.0; 4], [0.0; 4], [0.0; 4]);
+            for i in 0..ode::dGeomTriMeshGetTriangleCount(geom) {
+                ode::dGeomTriMeshGetTriangle(geom, i, &mut v0, &mut v1, &mut v2);
+                let (a, b, c) = (vec3(&v0), vec3(&v1), vec3(&v2));
+                self.line(a, b, color);
+                self.line(b, c, color);
+                self.line(c, a, color);
+            }
+        } else if class == ode::dConvexClass as i32 {
+            // ODE can't give the faces back, but the shape has them
+            if let BodyShape::ConvexHull { ref points, ref polygons, .. } = *shape {
+                let point = |i: u32| {
+                    let i = i as usize * 3;
+                    local(Vec3::new(points[i] as f32, points[i + 1] as f32, points[i + 2] as f32))
+                };
+                let mut i = 0;
+                while i < polygons.len() {
+                    let n = polygons[i] as usize;
+                    let face = &polygons[i + 1..i + 1 + n];
+                    for k in 0..n {
+                        self.line(point(face[k]), point(face[(k + 1) % n]), color);
+                    }
+                    i += n + 1;
+                }
+            }
+        }
+
+        // min x, max x, min y, max y, min z, max z
+        let mut aabb = [0.0; 6];
+        ode::dGeomGetAABB(geom, aabb.as_mut_ptr());
+        let corner = |i: usize| {
+            Vec3::new(aabb[i & 1] as f32,
+                      aabb[2 + ((i >> 1) & 1)] as f32,
+                      aabb[4 + ((i >> 2) & 1)] as f32)
+        };
+        self.box_edges(corner, dim(color));
+    }
+
+    // The heights that the terrain collides with, which the render mesh may not quite match
+    fn add_heightfield(&mut self, world: &World, focus: Vec3) {
+        let (width, depth) = world.heightfield_resolution;
+        if width < 2 || depth < 2 {
+            return;
+        }
+
+        let (gx, gz) = world.world_to_grid(focus.x, focus.z);
+        let r = self.grid_radius / world.heightfield_scale;
+        let (x0, x1) = (((gx - r).floor() as i32).max(0), ((gx + r).ceil() as i32).min(width - 1));
+        let (z0, z1) = (((gz - r).floor() as i32).max(0), ((gz + r).ceil() as i32).min(depth - 1));
+
+        let color = category_color(BODY_CATEGORY_TERRAIN_BIT);
+        let point = |x: i32, z: i32| {
+            let (wx, wz) = world.grid_to_world(x as f32, z as f32);
+            Vec3::new(wx, world.heightfield[(x + z * width) as usize], wz)
+        };
+        for z in z0..z1 + 1 {
+            for x in x0..x1 + 1 {
+                if x < x1 {
+                    self.line(point(x, z), point(x + 1, z), color);
+                }
+                if z < z1 {
+                    self.line(point(x, z), point(x, z + 1), color);
+                }
+            }
+        }
+    }
+
+    // The anchor and the axes of a joint, or a line between the bodies if it has neither
+    pub fn add_joint(&mut self, joint: &Joint) {
+        if joint.is_broken() {
+            return;
+        }
+
+        let get = |f: unsafe extern "C" fn(ode::dJointID, *mut ode::dReal)| {
+            let mut v: ode::dVector3 = [0.0; 4];
+            unsafe {
+                f(joint.ode_joint, v.as_mut_ptr());
+            }
+            vec3(&v)
+        };
+        let body1 = joint.body1.borrow().get_position();
+
+        let (anchor, axes) = match joint.kind {
+            JointKind::Hinge => {
+                (Some(get(ode::dJointGetHingeAnchor)), vec![get(ode::dJointGetHingeAxis)])
+            }
+            JointKind::Slider => (None, vec![get(ode::dJointGetSliderAxis)]),
+            JointKind::Ball => (Some(get(ode::dJointGetBallAnchor)), vec![]),
+            JointKind::Universal => {
+                (Some(get(ode::dJointGetUniversalAnchor)),
+                 vec![get(ode::dJointGetUniversalAxis1), get(ode::dJointGetUniversalAxis2)])
+            }
+            JointKind::Hinge2 => {
+                (Some(get(ode::dJointGetHinge2Anchor)),
+                 vec![get(ode::dJointGetHinge2Axis1), get(ode::dJointGetHinge2Axis2)])
+            }
+            JointKind::Fixed => (None, vec![]),
+        };
+
+        let origin = anchor.unwrap_or(body1);
+        if anchor.is_some() {
+            self.cross(origin, 0.3, JOINT_COLOR);
+        }
+        for axis in axes {
+            self.line(origin - axis, origin + axis, JOINT_COLOR);
+        }
+        if let Some(ref body2) = joint.body2 {
+            self.line(body1, body2.borrow().get_position(), dim(JOINT_COLOR));
+        }
+    }
+
+    // Draw everything added since the last draw, or just forget it when disabled
+    pub fn draw<F, S>(&mut self,
+                      facade: &F,
+                      surface: &mut S,
+                      perspective: [[f32; 4]; 4],
+                      modelview: [[f32; 4]; 4])
+                      -> Result<()>
+        where F: glium::backend::Facade,
+              S: glium::Surface
+    {
+        if !self.enabled || self.vertices.is_empty() {
+            self.vertices.clear();
+            return Ok(());
+        }
+
+        let buffer = glium::VertexBuffer::new(facade, &self.vertices)
+            .chain_err(|| "failed to allocate gpu buffer")?;
+        self.vertices.clear();
+
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLessOrEqual,
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        surface.draw(&buffer,
+                  &glium::index::NoIndices(glium::index::PrimitiveType::LinesList),
+                  &self.program,
+                  &uniform! {
+                         perspective: perspective,
+                         modelview: modelview,
+                     },
+                  &params)
+            .chain_err(|| "drawcall failed")
+    }
+}
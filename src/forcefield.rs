@@ -0,0 +1,113 @@
+use body::BODY_CATEGORY_ALL_BIT;
+use math::*;
+use na;
+use na::Norm;
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum FieldVolume {
+    Sphere { center: Vec3, radius: f32 },
+    // axis-aligned
+    Box { min: Vec3, max: Vec3 },
+}
+
+impl FieldVolume {
+    pub fn contains(&self, p: Vec3) -> bool {
+        match *self {
+            FieldVolume::Sphere { center, radius } => {
+                (p - center).norm_squared() <= radius * radius
+            }
+            FieldVolume::Box { min, max } => {
+                p.x >= min.x && p.y >= min.y && p.z >= min.z && p.x <= max.x && p.y <= max.y &&
+                p.z <= max.z
+            }
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        match *self {
+            FieldVolume::Sphere { center, .. } => center,
+            FieldVolume::Box { min, max } => (min + max) * 0.5,
+        }
+    }
+}
+
+// Accelerations in world units per second squared, so light and heavy bodies move alike
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum FieldKind {
+    // pulls the body's velocity towards the wind's, drag is the fraction per second
+    Wind { velocity: Vec3, drag: f32 },
+    // instead of the world gravity; overlapping zones add up, and the world gravity is gone in
+    // any of them
+    Gravity { acceleration: Vec3 },
+    // towards the center of the volume, or away from it when negative
+    Attractor { strength: f32 },
+    // around an axis through the center of the volume, counterclockwise when looking against
+    // the axis, and pull towards the axis
+    Vortex { axis: Vec3, strength: f32, pull: f32 },
+}
+
+// Pushes the dynamic bodies inside its volume; World applies these every tick
+#[derive(Debug, Clone)]
+pub struct ForceField {
+    pub volume: FieldVolume,
+    pub kind: FieldKind,
+    // the collision categories that feel this
+    pub mask: u64,
+    pub enabled: bool,
+}
+
+impl ForceField {
+    pub fn new(volume: FieldVolume, kind: FieldKind) -> ForceField {
+        ForceField {
+            volume: volume,
+            kind: kind,
+            mask: BODY_CATEGORY_ALL_BIT,
+            enabled: true,
+        }
+    }
+
+    // What this adds to the acceleration of a body at position moving at velocity; ODE applies
+    // the world gravity anyway, see replaces_gravity
+    pub fn acceleration(&self, position: Vec3, velocity: Vec3) -> Vec3 {
+        if !self.enabled || !self.volume.contains(position) {
+            return na::zero();
+        }
+
+        let center = self.volume.center();
+        match self.kind {
+            FieldKind::Wind { velocity: wind, drag } => (wind - velocity) * drag,
+            FieldKind::Gravity { acceleration } => acceleration,
+            FieldKind::Attractor { strength } => {
+                let to_center = center - position;
+                let dist = to_center.norm();
+                if dist < 1.0e-3 {
+                    na::zero()
+                } else {
+                    to_center * (strength / dist)
+                }
+            }
+            FieldKind::Vortex { axis, strength, pull } => {
+                let axis = axis.normalize();
+                let offset = position - center;
+                let radial = offset - axis * na::dot(&offset, &axis);
+                let dist = radial.norm();
+                if dist < 1.0e-3 {
+                    na::zero()
+                } else {
+                    let out = radial / dist;
+                    na::cross(&axis, &out) * strength - out * pull
+                }
+            }
+        }
+    }
+
+    // Whether a body at position should not feel the world gravity
+    pub fn replaces_gravity(&self, position: Vec3) -> bool {
+        match self.kind {
+            FieldKind::Gravity { .. } => self.enabled && self.volume.contains(position),
+            _ => false,
+        }
+    }
+}
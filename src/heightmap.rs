@@ -0,0 +1,138 @@
+// Terrain heights from a level map or from a separate height file, and the holes in the terrain.
+//
+// Images have their bottom row at z = 0, the same way as the level map textures; raw files start
+// from z = 0. Every sample value is mapped to world units as value * scale + offset, where image
+// values go from 0 to 1.
+
+use errors::*;
+use glium::texture::RawImage2d;
+use image;
+use image::{ColorType, DecodingResult, ImageDecoder};
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// a hole is a pit deep enough to fall forever
+pub const HOLE_HEIGHT: f32 = -400.0;
+
+pub struct Heightmap {
+    pub resolution: (i32, i32),
+    // world units, row-major, resolution.0 samples per row
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    fn new(resolution: (i32, i32), heights: Vec<f32>) -> Heightmap {
+        Heightmap {
+            resolution: resolution,
+            heights: heights,
+        }
+    }
+
+    // The red channel of an 8-bit level map
+    pub fn from_level_map(map: &RawImage2d<'static, u8>, scale: f32, offset: f32) -> Heightmap {
+        let heights = map.data
+            .chunks(4)
+            .map(|rgba| rgba[0] as f32 / 256.0 * scale + offset)
+            .collect();
+        Heightmap::new((map.width as i32, map.height as i32), heights)
+    }
+
+    // An 8- or 16-bit grayscale png
+    pub fn from_png<P: AsRef<Path> + ?Sized>(path: &P,
+                                             scale: f32,
+                                             offset: f32)
+                                             -> Result<Heightmap> {
+        let file = File::open(path).chain_err(|| "cannot open heightmap")?;
+        let mut decoder = image::png::PNGDecoder::new(BufReader::new(file));
+        let (width, depth) = decoder.dimensions().chain_err(|| "cannot read heightmap")?;
+        let colortype = decoder.colortype().chain_err(|| "cannot read heightmap")?;
+        let data = decoder.read_image().chain_err(|| "cannot decode heightmap")?;
+
+        let values: Vec<f32> = match (colortype, data) {
+            (ColorType::Gray(8), DecodingResult::U8(data)) => {
+                data.iter().map(|&v| v as f32 / 255.0).collect()
+            }
+            (ColorType::Gray(16), DecodingResult::U16(data)) => {
+                data.iter().map(|&v| v as f32 / 65535.0).collect()
+            }
+            // the samples as big endian byte pairs, like they are in the file
+            (ColorType::Gray(16), DecodingResult::U8(data)) => {
+                data.chunks(2)
+                    .map(|b| ((b[0] as u32) << 8 | b[1] as u32) as f32 / 65535.0)
+                    .collect()
+            }
+            (colortype, _) => bail!("heightmap must be grayscale, not {:?}", colortype),
+        };
+
+        let (width, depth) = (width as usize, depth as usize);
+        if values.len() != width * depth {
+            bail!("heightmap has {} samples instead of {}x{}", values.len(), width, depth);
+        }
+
+        let mut heights = Vec::with_capacity(values.len());
+        for row in values.chunks(width).rev() {
+            heights.extend(row.iter().map(|&v| v * scale + offset));
+        }
+        Ok(Heightmap::new((width as i32, depth as i32), heights))
+    }
+
+    // Little-endian 32-bit floats, width samples per row
+    pub fn from_raw_f32<P: AsRef<Path> + ?Sized>(path: &P,
+                                                  width: i32,
+                                                  scale: f32,
+                                                  offset: f32)
+                                                  -> Result<Heightmap> {
+        let mut data = Vec::new();
+        File::open(path)
+            .chain_err(|| "cannot open heightmap")?
+            .read_to_end(&mut data)
+            .chain_err(|| "cannot read heightmap")?;
+
+        let row_bytes = width as usize * 4;
+        if width < 2 || data.len() % row_bytes != 0 || data.len() / row_bytes < 2 {
+            bail!("heightmap of {} bytes is not made of rows of {} floats", data.len(), width);
+        }
+
+        let heights = data.chunks(4)
+            .map(|b| {
+                let bits = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 |
+                           (b[3] as u32) << 24;
+                f32::from_bits(bits) * scale + offset
+            })
+            .collect();
+        Ok(Heightmap::new((width, (data.len() / row_bytes) as i32), heights))
+    }
+
+    // Make holes where the mask is set
+    pub fn cut_holes(&mut self, mask: &[bool]) -> Result<()> {
+        if mask.len() != self.heights.len() {
+            bail!("hole mask has {} samples, the heightmap {}", mask.len(), self.heights.len());
+        }
+        for (h, &hole) in self.heights.iter_mut().zip(mask.iter()) {
+            if hole {
+                *h = HOLE_HEIGHT;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The level maps paint holes black; the two darkest reds have always counted as black
+pub fn holes_from_level_map(map: &RawImage2d<'static, u8>) -> Vec<bool> {
+    map.data.chunks(4).map(|rgba| rgba[0] < 2).collect()
+}
+
+// A mask image of any format where black is a hole
+pub fn holes_from_image<P: AsRef<Path> + ?Sized>(path: &P) -> Result<Vec<bool>> {
+    let image = image::open(path).chain_err(|| "cannot load hole mask")?.to_luma();
+    let width = image.width() as usize;
+    let data = image.into_raw();
+
+    let mut holes = Vec::with_capacity(data.len());
+    for row in data.chunks(width).rev() {
+        holes.extend(row.iter().map(|&v| v == 0));
+    }
+    Ok(holes)
+}
@@ -3,8 +3,10 @@ use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Scancode};
 use math::*;
 use na;
+use errors::*;
+use replay::{Recorder, Replay};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Input {
     pub quit: bool,
     pub jump: bool,
@@ -17,6 +19,9 @@ pub struct Input {
     pub action: bool,
 
     pub zoom: f32,
+
+    // not part of the game, so not recorded either
+    pub debug_draw: bool,
 }
 
 impl Default for Input {
@@ -33,20 +38,79 @@ impl Default for Input {
             action: false,
 
             zoom: 0.0,
+
+            debug_draw: false,
         }
     }
 }
 
 pub struct InputState {
     controller: Option<sdl2::controller::GameController>,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    frame_input: Input,
+    // camera and zoom deltas of the frames since the last tick
+    pending_camera: Vec2,
+    pending_zoom: f32,
 }
 
 impl InputState {
     pub fn new(controller: Option<sdl2::controller::GameController>) -> InputState {
-        InputState { controller: controller }
+        InputState {
+            controller: controller,
+            recorder: None,
+            replay: None,
+            frame_input: Default::default(),
+            pending_camera: na::zero(),
+            pending_zoom: 0.0,
+        }
+    }
+
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn play_from(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+    }
+
+    // Input for one fixed physics tick. When playing back, this comes from the replay instead of
+    // the events of this frame; otherwise the frame input is repeated for each tick in the frame,
+    // except for the camera and zoom deltas. Those add up over the frames until a tick takes them,
+    // so that they are applied exactly once even when a frame has no ticks at all.
+    pub fn tick_input(&mut self) -> Result<Input> {
+        let input = if let Some(ref mut replay) = self.replay {
+            replay.next_input().unwrap_or_default()
+        } else {
+            let mut input = self.frame_input.clone();
+            input.camera = self.pending_camera;
+            input.zoom = self.pending_zoom;
+            input
+        };
+        self.discard_pending();
+
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record(&input)?;
+        }
+
+        Ok(input)
     }
 
     pub fn process_input(&mut self, pump: &mut sdl2::EventPump) -> Input {
+        let input = self.poll_input(pump);
+        self.frame_input = input.clone();
+        self.pending_camera = self.pending_camera + input.camera;
+        self.pending_zoom += input.zoom;
+        input
+    }
+
+    // Forget the camera and zoom deltas that no tick has taken, e.g. while not in the game
+    pub fn discard_pending(&mut self) {
+        self.pending_camera = na::zero();
+        self.pending_zoom = 0.0;
+    }
+
+    fn poll_input(&mut self, pump: &mut sdl2::EventPump) -> Input {
         let mut input: Input = Default::default();
 
         for ev in pump.poll_iter() {
@@ -59,6 +123,7 @@ impl InputState {
                         Some(Keycode::R) => input.reset_camera = true,
                         Some(Keycode::S) => input.stop = true,
                         Some(Keycode::A) => input.action = true,
+                        Some(Keycode::F1) => input.debug_draw = true,
                         _ => (),
                     }
                 }
@@ -0,0 +1,247 @@
+use body::Body;
+use math::*;
+
+use ode;
+pub use ode::dParam;
+pub use ode::{dParamLoStop, dParamHiStop, dParamVel, dParamFMax, dParamStopERP, dParamStopCFM};
+
+use na::Norm;
+use std;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JointKind {
+    // rotates around an axis
+    Hinge,
+    // moves along an axis
+    Slider,
+    // rotates freely around an anchor point
+    Ball,
+    // rotates around two perpendicular axes, like a cardan shaft
+    Universal,
+    // doesn't move at all
+    Fixed,
+    // a steering axis and a wheel axis, like a car's front wheel
+    Hinge2,
+}
+
+// Connects a body to another one, or to the static world if there's no second body. The joint
+// keeps its bodies alive and is destroyed when dropped.
+pub struct Joint {
+    pub kind: JointKind,
+    pub body1: Rc<RefCell<Body>>,
+    pub body2: Option<Rc<RefCell<Body>>>,
+    pub ode_joint: ode::dJointID,
+    // ODE writes the forces of each step here, so it must not move
+    feedback: Box<ode::dJointFeedback>,
+    break_force: Option<f32>,
+    break_torque: Option<f32>,
+    broken: bool,
+}
+
+impl Joint {
+    // The anchor starts at the position of the first body
+    pub fn new(world: ode::dWorldID,
+               kind: JointKind,
+               body1: Rc<RefCell<Body>>,
+               body2: Option<Rc<RefCell<Body>>>)
+               -> Self {
+        let joint = unsafe {
+            let group = std::ptr::null_mut();
+            match kind {
+                JointKind::Hinge => ode::dJointCreateHinge(world, group),
+                JointKind::Slider => ode::dJointCreateSlider(world, group),
+                JointKind::Ball => ode::dJointCreateBall(world, group),
+                JointKind::Universal => ode::dJointCreateUniversal(world, group),
+                JointKind::Fixed => ode::dJointCreateFixed(world, group),
+                JointKind::Hinge2 => ode::dJointCreateHinge2(world, group),
+            }
+        };
+
+        let mut feedback: Box<ode::dJointFeedback> = Box::new(unsafe { std::mem::zeroed() });
+        unsafe {
+            let ode_body2 = body2.as_ref().map_or(std::ptr::null_mut(), |b| b.borrow().ode_body);
+            ode::dJointAttach(joint, body1.borrow().ode_body, ode_body2);
+            ode::dJointSetFeedback(joint, &mut *feedback);
+            if kind == JointKind::Fixed {
+                // keeps the current relative position
+                ode::dJointSetFixed(joint);
+            }
+        }
+
+        let anchor = body1.borrow().get_position();
+        let mut j = Joint {
+            kind: kind,
+            body1: body1,
+            body2: body2,
+            ode_joint: joint,
+            feedback: feedback,
+            break_force: None,
+            break_torque: None,
+            broken: false,
+        };
+        j.set_anchor(anchor);
+        j
+    }
+
+    // No effect on sliders and fixed joints
+    pub fn set_anchor(&mut self, anchor: Vec3) {
+        let (x, y, z) = (anchor.x as f64, anchor.y as f64, anchor.z as f64);
+        unsafe {
+            match self.kind {
+                JointKind::Hinge => ode::dJointSetHingeAnchor(self.ode_joint, x, y, z),
+                JointKind::Ball => ode::dJointSetBallAnchor(self.ode_joint, x, y, z),
+                JointKind::Universal => ode::dJointSetUniversalAnchor(self.ode_joint, x, y, z),
+                JointKind::Hinge2 => ode::dJointSetHinge2Anchor(self.ode_joint, x, y, z),
+                JointKind::Slider | JointKind::Fixed => (),
+            }
+        }
+    }
+
+    // The hinge or slider axis, or the first axis of universal and hinge-2 joints. No effect on
+    // ball and fixed joints.
+    pub fn set_axis(&mut self, axis: Vec3) {
+        let (x, y, z) = (axis.x as f64, axis.y as f64, axis.z as f64);
+        unsafe {
+            match self.kind {
+                JointKind::Hinge => ode::dJointSetHingeAxis(self.ode_joint, x, y, z),
+                JointKind::Slider => ode::dJointSetSliderAxis(self.ode_joint, x, y, z),
+                JointKind::Universal => ode::dJointSetUniversalAxis1(self.ode_joint, x, y, z),
+                JointKind::Hinge2 => ode::dJointSetHinge2Axis1(self.ode_joint, x, y, z),
+                JointKind::Ball | JointKind::Fixed => (),
+            }
+        }
+    }
+
+    // The second axis of universal and hinge-2 joints
+    #[allow(dead_code)]
+    pub fn set_axis2(&mut self, axis: Vec3) {
+        let (x, y, z) = (axis.x as f64, axis.y as f64, axis.z as f64);
+        unsafe {
+            match self.kind {
+                JointKind::Universal => ode::dJointSetUniversalAxis2(self.ode_joint, x, y, z),
+                JointKind::Hinge2 => ode::dJointSetHinge2Axis2(self.ode_joint, x, y, z),
+                _ => (),
+            }
+        }
+    }
+
+    // A raw ODE parameter of the first (1) or the second (2) axis
+    pub fn set_param(&mut self, param: dParam, axis: u32, value: f32) {
+        let param = param as i32 + (axis as i32 - 1) * ode::dParamGroup2 as i32;
+        let value = value as f64;
+        unsafe {
+            match self.kind {
+                JointKind::Hinge => ode::dJointSetHingeParam(self.ode_joint, param, value),
+                JointKind::Slider => ode::dJointSetSliderParam(self.ode_joint, param, value),
+                JointKind::Ball => ode::dJointSetBallParam(self.ode_joint, param, value),
+                JointKind::Universal => {
+                    ode::dJointSetUniversalParam(self.ode_joint, param, value)
+                }
+                JointKind::Fixed => ode::dJointSetFixedParam(self.ode_joint, param, value),
+                JointKind::Hinge2 => ode::dJointSetHinge2Param(self.ode_joint, param, value),
+            }
+        }
+    }
+
+    // Angles in radians, or distances for sliders
+    #[allow(dead_code)]
+    pub fn set_limits(&mut self, axis: u32, lo: f32, hi: f32) {
+        // ODE ignores a low stop above the high one, so set them in an order that works
+        self.set_param(dParamLoStop, axis, -std::f32::INFINITY);
+        self.set_param(dParamHiStop, axis, hi);
+        self.set_param(dParamLoStop, axis, lo);
+    }
+
+    // Drive towards a velocity with at most the given force or torque; zero force turns the
+    // motor off
+    pub fn set_motor(&mut self, axis: u32, velocity: f32, max_force: f32) {
+        self.set_param(dParamVel, axis, velocity);
+        self.set_param(dParamFMax, axis, max_force);
+    }
+
+    // How hard and how soft the limits are
+    #[allow(dead_code)]
+    pub fn set_stop_erp_cfm(&mut self, axis: u32, erp: f32, cfm: f32) {
+        self.set_param(dParamStopERP, axis, erp);
+        self.set_param(dParamStopCFM, axis, cfm);
+    }
+
+    // The joint breaks when it has to hold more than this on either body
+    #[allow(dead_code)]
+    pub fn set_breaking(&mut self, force: Option<f32>, torque: Option<f32>) {
+        self.break_force = force;
+        self.break_torque = torque;
+    }
+
+    // Check the forces of the last world step against the breaking thresholds. A broken joint
+    // gets disabled; returns true on the step it breaks. Call this between world steps.
+    #[allow(dead_code)]
+    pub fn update_breaking(&mut self) -> bool {
+        if self.broken {
+            return false;
+        }
+
+        let fb = &*self.feedback;
+        let len = |v: &ode::dVector3| Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32).norm();
+        let force = len(&fb.f1).max(len(&fb.f2));
+        let torque = len(&fb.t1).max(len(&fb.t2));
+
+        if self.break_force.map_or(false, |limit| force > limit) ||
+           self.break_torque.map_or(false, |limit| torque > limit) {
+            self.broken = true;
+            unsafe {
+                ode::dJointDisable(self.ode_joint);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+}
+
+impl Drop for Joint {
+    fn drop(&mut self) {
+        // the world destroys its joints along with it
+        if self.body1.borrow().world_alive.get() {
+            unsafe {
+                ode::dJointDestroy(self.ode_joint);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::tests::{flat_world, add_ball};
+
+    #[test]
+    fn joint_breaks_when_it_holds_too_much() {
+        let mut world = flat_world();
+        // the balls weigh about 4 newtons, and hang from the static world
+        let weak_ball = add_ball(&mut world, Vec3::new(-5.0, 10.0, 0.0));
+        let strong_ball = add_ball(&mut world, Vec3::new(5.0, 10.0, 0.0));
+        let mut weak = Joint::new(world.ode_world(), JointKind::Ball, weak_ball.clone(), None);
+        let mut strong = Joint::new(world.ode_world(), JointKind::Ball, strong_ball.clone(), None);
+        weak.set_breaking(Some(1.0), None);
+        strong.set_breaking(Some(100.0), Some(100.0));
+
+        for tick in 0..100 {
+            world.step(PHYS_DT, |_| Ok(())).unwrap();
+            assert_eq!(weak.update_breaking(), tick == 0);
+            assert!(!strong.update_breaking());
+        }
+
+        assert!(weak.is_broken());
+        assert!(!strong.is_broken());
+        assert!(weak_ball.borrow().get_position().y < 9.0);
+        assert!((strong_ball.borrow().get_position().y - 10.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,62 @@
+use body::BODY_CATEGORY_ALL_BIT;
+use math::*;
+
+// Where the top of a liquid is
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum LiquidSurface {
+    // flat, at this height
+    Level(f32),
+    // this far above the terrain, so that it rides on the waves
+    Terrain { depth: f32 },
+}
+
+// A body of liquid from a bottom height up to its surface over a rectangle of the xz plane.
+// Bodies in it float by the volume they have under the surface and slow down by how much of
+// them is under. World applies these every tick.
+#[derive(Debug, Clone)]
+pub struct Liquid {
+    // x and z corners of the area
+    pub min: Vec2,
+    pub max: Vec2,
+    pub bottom: f32,
+    pub surface: LiquidSurface,
+    // mass per volume, in the same units as body densities; denser bodies sink
+    pub density: f32,
+    // fractions of the velocity lost per second when all under
+    pub linear_drag: f32,
+    pub angular_drag: f32,
+    // the collision categories that feel this
+    pub mask: u64,
+    pub enabled: bool,
+}
+
+impl Liquid {
+    pub fn new(min: Vec2, max: Vec2, bottom: f32, surface: LiquidSurface) -> Liquid {
+        Liquid {
+            min: min,
+            max: max,
+            bottom: bottom,
+            surface: surface,
+            density: 1.0,
+            linear_drag: 1.0,
+            angular_drag: 1.0,
+            mask: BODY_CATEGORY_ALL_BIT,
+            enabled: true,
+        }
+    }
+
+    pub fn covers(&self, x: f32, z: f32) -> bool {
+        x >= self.min.x && z >= self.min.y && x <= self.max.x && z <= self.max.y
+    }
+
+    // The part of a box from bottom to top that is under the surface, as its fraction of the
+    // box height and the height of the middle of that part
+    pub fn submerged(&self, bottom: f32, top: f32, surface: f32) -> (f32, f32) {
+        let (lo, hi) = (bottom.max(self.bottom), top.min(surface));
+        if hi <= lo || top <= bottom {
+            return (0.0, 0.0);
+        }
+        ((hi - lo) / (top - bottom), (lo + hi) * 0.5)
+    }
+}
@@ -18,6 +18,8 @@ extern crate inotify;
 
 extern crate rand;
 
+extern crate rayon;
+
 extern crate nanovg;
 
 #[link(name = "ode")]
@@ -32,8 +34,17 @@ mod obj;
 mod texture;
 mod particle;
 mod input;
-mod gear;
+mod joint;
 mod settings;
+mod replay;
+mod material;
+mod wave;
+mod platform;
+mod player;
+mod forcefield;
+mod liquid;
+mod heightmap;
+mod debugdraw;
 
 mod ode;
 
@@ -55,7 +66,6 @@ use std::io::Read;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::path::Path;
-use std::collections::HashSet;
 
 use na::{ToHomogeneous, Rotation3, Norm};
 use glium::Surface;
@@ -64,8 +74,7 @@ use inotify::ffi::*;
 
 use math::*;
 use audio::{AudioMixer, JumpSound, HitSound, SimpleSound};
-use body::Body;
-use gear::{Gear, dJointTypeHinge, dParamFMax, dParamVel};
+use joint::{Joint, JointKind};
 use settings::Settings;
 
 static VERTEX_SHADER: &'static str = r#"
@@ -221,7 +230,32 @@ fn run() -> Result<()> {
         ode::dInitODE();
     }
 
-    let settings = Settings::new("settings.txt").chain_err(|| "no settings file found")?;
+    let mut record_file = None;
+    let mut replay = None;
+    {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "--record" => {
+                    record_file = Some(args.next().ok_or("--record needs a file name")?);
+                }
+                "--replay" => {
+                    let name = args.next().ok_or("--replay needs a file name")?;
+                    replay = Some(replay::Replay::load(&name).chain_err(|| "failed to load replay")?);
+                }
+                _ => bail!("unknown argument '{}'", arg),
+            }
+        }
+    }
+
+    // a replay brings its own level and settings so that it plays back the same way
+    let (settings, level_name) = match replay {
+        Some(ref r) => (r.settings.clone(), r.level.clone()),
+        None => {
+            (Settings::new("settings.txt").chain_err(|| "no settings file found")?,
+             "level2.png".to_owned())
+        }
+    };
     let sdl_ctx = sdl2::init().map_err(sdl_err).chain_err(|| "failed to initialize SDL")?;
     let sdl_video = sdl_ctx.video().map_err(sdl_err).chain_err(|| "failed to initialize video")?;
     let _sdl_audio = sdl_ctx.audio().map_err(sdl_err).chain_err(|| "failed to initialize audio")?;
@@ -280,6 +314,14 @@ fn run() -> Result<()> {
     }
 
     let mut input_state = input::InputState::new(sel_gcon);
+    if let Some(ref name) = record_file {
+        input_state.record_to(replay::Recorder::create(name, &level_name, &settings)
+            .chain_err(|| "failed to start recording")?);
+    }
+    let replaying = replay.is_some();
+    if let Some(replay) = replay {
+        input_state.play_from(replay);
+    }
 
     let mut mesh = vec![];
     mesh.push(Vertex { position: [0.0f32, 0.9f32] });
@@ -322,34 +364,88 @@ fn run() -> Result<()> {
         );
     player.borrow_mut().set_finite_rotation_mode(true);
 
-    let level_map = texture::load_image("level2.png").chain_err(|| "failed to load level")?;
-    let level_body_id = {
+    let level_map = texture::load_image(&level_name).chain_err(|| "failed to load level")?;
+    let heightmap = {
+        let (vscale, offset) = (settings.get_f32("heightmap_scale"),
+                                settings.get_f32("heightmap_offset"));
+        let mut heightmap = match settings.get_str("heightmap") {
+            "level" => heightmap::Heightmap::from_level_map(&level_map, vscale, offset),
+            path if path.ends_with(".png") => {
+                heightmap::Heightmap::from_png(path, vscale, offset)
+                    .chain_err(|| "failed to load heightmap")?
+            }
+            path => {
+                let width = settings.get_u32("heightmap_width") as i32;
+                heightmap::Heightmap::from_raw_f32(path, width, vscale, offset)
+                    .chain_err(|| "failed to load heightmap")?
+            }
+        };
+        let holes = match settings.get_str("heightmap_holes") {
+            "none" => None,
+            "level" => Some(heightmap::holes_from_level_map(&level_map)),
+            path => Some(heightmap::holes_from_image(path).chain_err(|| "failed to load holes")?),
+        };
+        if let Some(holes) = holes {
+            heightmap.cut_holes(&holes)?;
+        }
+        heightmap
+    };
+    {
         let landscape_texture = Rc::new(
         texture::load_texture(&display, "ruohe.png")
         .chain_err(|| "failed to load landscape texture")?);
+        let mut w = world.borrow_mut();
         // do not move this. this installs a self pointer to a C callback that shouldn't change
-        let body = world.borrow_mut().setup_heightfield(&display, &level_map, landscape_texture);
-        let id = body.borrow().id;
-        id
-    };
+        w.setup_heightfield(&heightmap, settings.get_u32("terrain_chunk") as i32);
+        w.setup_heightfield_mesh(&display, landscape_texture)?;
+        w.terrain_wake_distance = settings.get_f32("terrain_wake_distance");
+        w.set_max_substeps(settings.get_u32("max_substeps"));
+    }
+    {
+        let power = settings.get_f32("heightaction_power");
+        let damp = settings.get_f32("heightaction_damp");
+        let restore = settings.get_f32("heightaction_sin");
+        let parallel = settings.get_u32("wave_parallel") != 0;
+        let solver: Box<wave::WaveSolver> = match settings.get_str("wave_solver") {
+            "spring" => {
+                Box::new(wave::SpringSolver {
+                    parallel: parallel,
+                    ..wave::SpringSolver::new(power, damp, restore)
+                })
+            }
+            "equation" => {
+                Box::new(wave::WaveEquationSolver {
+                    parallel: parallel,
+                    ..wave::WaveEquationSolver::new(power, damp, restore)
+                })
+            }
+            x => bail!("unknown wave solver '{}'", x),
+        };
+        world.borrow_mut().set_wave_solver(solver);
+
+        let amplitude = settings.get_f32("oscillator_amplitude");
+        if amplitude != 0.0 {
+            world.borrow_mut().add_wave_source(settings.get_vec3("oscillator_pos"),
+                                               amplitude,
+                                               settings.get_f32("oscillator_frequency"),
+                                               settings.get_f32("oscillator_radius"),
+                                               None);
+        }
+    }
+
     // set player position to 20, 20 and read height from heightfield
     // player.borrow_mut().set_position(settings.get_vec3("player"));
     {
-        let reso = world.borrow_mut()
-            .heightfield_resolution;
-
-        player.borrow_mut()
-            .set_position(Vec3::new(20.0 * scale - reso.0 as f32 * scale / 2.0,
-                                    world.borrow_mut().heightfield[((20.0 ) * reso.0 as f32 + 20.0 ) as usize] +
-                                    5.0,
-                                    20.0 * scale - reso.1 as f32 * scale / 2.0));
+        let w = world.borrow();
+        let (x, z) = w.grid_to_world(20.0, 20.0);
+        player.borrow_mut().set_position(Vec3::new(x, w.height_at(x, z) + 5.0, z));
     }
 
-    let diamonds = Rc::new(RefCell::new(Vec::new()));
+    let mut diamonds = Vec::new();
     let mut diams_tot = 0;
     let mut diams_got = 0;
     let diam_shape = Rc::new(
-        body::BodyShape::from_obj("diamond.obj")
+        body::BodyShape::convex_from_obj("diamond.obj")
         .chain_err(|| "failed to load diamond mesh for phys")?);
     let diam_mesh = Rc::new(
         RefCell::new(mesh::Mesh::from_obj(&display, "diamond.obj", false)
@@ -365,15 +461,17 @@ fn run() -> Result<()> {
         for x in 0..width {
             for z in 0..depth {
                 let hmp = (z * width + x) as usize;
-                let r = level_map.data[hmp * 4 + 0] as f32 / 256.0;
                 let g = level_map.data[hmp * 4 + 1] as f32 / 256.0;
                 let b = level_map.data[hmp * 4 + 2] as f32 / 256.0;
-                let px = x as f32 * scale;
-                let pz = z as f32 * scale;
 
-                let p = Vec3::new((px + 0.5 * scale) - scale * 0.5 * (width - 1) as f32,
-                                  r * 32.0 * scale + 1.5,
-                                  (pz + 0.5 * scale) - scale * 0.5 * (depth - 1) as f32);
+                // in the middle of the square between this and the next samples, at the height of
+                // this one; the next ones can be holes
+                let p = {
+                    let w = world.borrow();
+                    let (sx, sz) = w.grid_to_world(x as f32, z as f32);
+                    let (px, pz) = w.grid_to_world(x as f32 + 0.5, z as f32 + 0.5);
+                    Vec3::new(px, w.height_at(sx, sz) + 1.5, pz)
+                };
                 if g > 0.5 || b > 0.5 {
                     let diamond = if g > 0.5 {
                         world.borrow_mut().add_body(
@@ -382,6 +480,8 @@ fn run() -> Result<()> {
                             diam_shape.clone(),
                             body::BodyConfig {
                                 collide_sound: Some(0),
+                                sensor: true,
+                                collide_bits: body::BODY_CATEGORY_PLAYER_BIT,
                                 ..Default::default()
                             })
                     } else { // blue ones with green are still diams
@@ -390,19 +490,21 @@ fn run() -> Result<()> {
                             pup0_texture.clone(),
                             diam_shape.clone(),
                             body::BodyConfig { collide_sound: Some(1),
+                            sensor: true,
+                            collide_bits: body::BODY_CATEGORY_PLAYER_BIT,
                             ..Default::default() })
                     };
                     if g <= 0.5 {
-                        pups.push(diamond.borrow().id);
+                        pups.push(diamond.borrow().handle);
                     }
                     diamond.borrow_mut().set_position(p);
-                    diamonds.borrow_mut().push(diamond.borrow().id);
-                    let mut gear = Gear::new(world.borrow_mut().ode_world(),
-                                             diamond.clone(),
-                                             dJointTypeHinge);
-                    gear.set_hinge_axis(Vec3::new(0.0, 1.0, 0.0));
-                    gear.set_hinge_param(dParamFMax, 1000.0);
-                    gear.set_hinge_param(dParamVel, 1.0);
+                    diamonds.push(diamond.borrow().handle);
+                    let mut gear = Joint::new(world.borrow_mut().ode_world(),
+                                              JointKind::Hinge,
+                                              diamond.clone(),
+                                              None);
+                    gear.set_axis(Vec3::new(0.0, 1.0, 0.0));
+                    gear.set_motor(1, 1.0, 1000.0);
                     diamgears.push(gear);
                     diams_tot += 1;
                 }
@@ -410,6 +512,106 @@ fn run() -> Result<()> {
         }
     }
 
+    // moving platforms; the settings give their height above the ground
+    let (lift_platform, lift_body) = {
+        let platform_texture = Rc::new(
+            texture::load_texture(&display, "ruohe.png")
+            .chain_err(|| "failed to load platform texture")?);
+        let above_ground = |p: Vec3| {
+            Vec3::new(p.x, p.y + world.borrow().height_at(p.x, p.z), p.z)
+        };
+        // only the player bumps into these
+        let config = body::BodyConfig {
+            fixed: true,
+            category_bits: body::BODY_CATEGORY_GEAR_BIT,
+            collide_bits: body::BODY_COLLIDE_GEAR,
+            ..Default::default()
+        };
+
+        let arm_size = Vec3::new(16.0, 1.0, 2.0);
+        let arm = world.borrow_mut().add_body(
+            Rc::new(RefCell::new(mesh::Mesh::for_box(&display, arm_size)
+                                 .chain_err(|| "failed to create spinthing mesh")?)),
+            platform_texture.clone(),
+            Rc::new(body::BodyShape::Box { size: arm_size }),
+            config);
+        arm.borrow_mut().set_position(above_ground(settings.get_vec3("spinthing")));
+        let handle = arm.borrow().handle;
+        world.borrow_mut().add_platform(handle,
+                                        platform::PlatformMotion::Rotation {
+                                            axis: Vec3::new(0.0, 1.0, 0.0),
+                                            angle: 0.0,
+                                            speed: 0.5,
+                                        },
+                                        platform::PathMode::Loop);
+
+        let lift_size = Vec3::new(6.0, 1.0, 6.0);
+        let lift = world.borrow_mut().add_body(
+            Rc::new(RefCell::new(mesh::Mesh::for_box(&display, lift_size)
+                                 .chain_err(|| "failed to create liftgear mesh")?)),
+            platform_texture.clone(),
+            Rc::new(body::BodyShape::Box { size: lift_size }),
+            config);
+        let bottom = above_ground(settings.get_vec3("liftgear"));
+        lift.borrow_mut().set_position(bottom);
+        let handle = lift.borrow().handle;
+        let top = bottom + Vec3::new(0.0, 12.0, 0.0);
+        // goes up when stepped on at the bottom and down when stepped on at the top
+        let id = world.borrow_mut().add_platform(handle,
+                                                 platform::PlatformMotion::Waypoints {
+                                                     points: vec![bottom, top],
+                                                     speed: 2.0,
+                                                 },
+                                                 platform::PathMode::Triggered);
+        (id, handle)
+    };
+
+    // force fields and water; the settings give heights above the ground
+    {
+        let mut w = world.borrow_mut();
+        let center = settings.get_vec3("lowgravity");
+        let radius = settings.get_f32("lowgravity_radius");
+        if radius > 0.0 {
+            let center = Vec3::new(center.x, center.y + w.height_at(center.x, center.z), center.z);
+            w.add_force_field(forcefield::ForceField::new(
+                forcefield::FieldVolume::Sphere { center: center, radius: radius },
+                forcefield::FieldKind::Gravity {
+                    acceleration: Vec3::new(0.0, -0.25 * GRAVITY, 0.0),
+                }));
+        }
+
+        // a column of air blowing upwards
+        let base = settings.get_vec3("fan");
+        let speed = settings.get_f32("fan_speed");
+        if speed > 0.0 {
+            let base = Vec3::new(base.x, base.y + w.height_at(base.x, base.z), base.z);
+            w.add_force_field(forcefield::ForceField::new(
+                forcefield::FieldVolume::Box {
+                    min: base - Vec3::new(3.0, 0.0, 3.0),
+                    max: base + Vec3::new(3.0, 25.0, 3.0),
+                },
+                forcefield::FieldKind::Wind {
+                    velocity: Vec3::new(0.0, speed, 0.0),
+                    drag: 2.0,
+                }));
+        }
+
+        // still water, light enough for the ball to sink into halfway
+        let pond = settings.get_vec3("pond");
+        let half = settings.get_f32("pond_size") * 0.5;
+        if half > 0.0 {
+            let level = pond.y + w.height_at(pond.x, pond.z);
+            let mut water = liquid::Liquid::new(Vec2::new(pond.x - half, pond.z - half),
+                                                Vec2::new(pond.x + half, pond.z + half),
+                                                std::f32::NEG_INFINITY,
+                                                liquid::LiquidSurface::Level(level));
+            water.density = 0.2;
+            water.linear_drag = 0.8;
+            water.angular_drag = 1.5;
+            w.add_liquid(water);
+        }
+    }
+
     let envmap = texture::load_texture_array(
         &display, &[
             "cubemap/negx.jpg",
@@ -426,7 +628,7 @@ fn run() -> Result<()> {
     let mut particles = particle::Particles::new(
         &display, vec![texture::load_image("Smoke10.png")?], 100)
                     .chain_err(|| "failed to initialize particle engine")?;
-    let new_particles = Rc::new(RefCell::new(vec![]));
+    let mut debug_draw = debugdraw::DebugDraw::new(&display)?;
 
     let program = glium::Program::from_source(&display, VERTEX_SHADER, FRAGMENT_SHADER, None)
         .unwrap();
@@ -463,81 +665,13 @@ fn run() -> Result<()> {
         Rc::new(SimpleSound::new("sounds/unbelievable.wav")
                 .chain_err(|| "failed to load unbelievable sound")?),
     ];
-    let on_ground = Rc::new(RefCell::new(false));
-    {
-        let plr_id = player.borrow_mut().id;
-        let ground_id = level_body_id;
-        let mixer = mixer.clone();
-        let hit_sound = hit_sound.clone();
-        let diamonds = diamonds.clone();
-        let new_particles = new_particles.clone();
-        let vol_scale = settings.get_f32("volume_scale");
-        let on_ground = on_ground.clone();
-        let landscape_sound_handler = move |o1: &mut Body,
-                                            o2: &mut Body,
-                                            contact: &mut ode::dContact| {
-            // diamonds don't cause a sound here
-            if !diamonds.borrow().contains(&o1.id) && !diamonds.borrow().contains(&o2.id) {
-                if o1.id == plr_id || o2.id == plr_id {
-                    let vel1 = o1.get_linear_velocity();
-                    let vel2 = o2.get_linear_velocity();
-                    let delta_vel = vel1 - vel2;
-                    let normal = Vec3::new(contact.geom.normal[0] as f32,
-                                           contact.geom.normal[1] as f32,
-                                           contact.geom.normal[2] as f32);
-                    let coincide_vel = na::dot(&normal, &delta_vel).abs();
-                    let volume = (0.04 * vol_scale * coincide_vel * coincide_vel).min(1.0);
-                    // TODO: multiple different sounds for even more dramatic collisions
-                    if volume > 0.02 {
-                        // bleh, can't ".chain_err(foo)?" this result in a handler
-                        mixer.play(&*hit_sound, (volume,)).expect("failed to play hit sound");
-
-                        for a in 0..10 {
-                            let angle = (a as f32 / 10.0) * 2.0 * 3.1416;
-                            let part = particle::Particle {
-                                position: o1.get_position().to_point() + Vec3::new(0.0, -1.0, 0.0),
-                                scale: Vec2::new(volume, volume) * 2.5,
-                                velocity: Vec3::new(angle.cos() * 3.0, 0.2, angle.sin() * 3.0),
-                                lifetime: Some(0.75),
-                                texture: 0,
-                                ..Default::default()
-                            };
-                            new_particles.borrow_mut().push(part);
-                        }
-                    }
-                    if o1.id == ground_id || o2.id == ground_id {
-                        *on_ground.borrow_mut() = true;
-                    }
-                }
-            }
-            false
-        };
-        world.borrow_mut().add_contact_handler(Box::new(landscape_sound_handler));
-    }
-    let del_diamonds: Rc<RefCell<HashSet<u64>>> = Rc::new(RefCell::new(HashSet::new()));
-    {
-        let plr_id = player.borrow_mut().id;
-        let del_diamonds = del_diamonds.clone();
-        let diamonds = diamonds.clone();
-        let diamond_collision_handler =
-            move |o1: &mut Body, o2: &mut Body, _contact: &mut ode::dContact| {
-                if o1.id == plr_id || o2.id == plr_id {
-                    let (_player, diamond) = if o1.id == plr_id { (o1, o2) } else { (o2, o1) };
-                    if diamonds.borrow().contains(&diamond.id) {
-                        del_diamonds.borrow_mut().insert(diamond.id);
-                        // don't cause physical collision
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            };
-        world.borrow_mut().add_contact_handler(Box::new(diamond_collision_handler));
-    }
-
-    let mut allow_jump = true;
+    let player_handle = player.borrow().handle;
+    let vol_scale = settings.get_f32("volume_scale");
+    let mut controller = player::PlayerController::new(player_handle, player::PlayerParams {
+        jump_buffer: settings.get_f32("jump_buffer"),
+        coyote_time: settings.get_f32("coyote_time"),
+        ..Default::default()
+    });
 
     let mut last_particle = 0.0;
 
@@ -555,11 +689,13 @@ fn run() -> Result<()> {
 
     let mut fov = PI / 2.0;
 
-    let force_mag_duration = 10 * 1000;
-    let mut force_mag_end = sdl_timer.ticks();
-
     let mut endtime = 0;
 
+    if replaying {
+        mixer.play_music()?;
+        gstate = State::Game;
+    }
+
     'mainloop: loop {
         let evs = ino.available_events().unwrap();
 
@@ -584,6 +720,9 @@ fn run() -> Result<()> {
         if input.quit {
             break 'mainloop;
         }
+        if input.debug_draw {
+            debug_draw.enabled = !debug_draw.enabled;
+        }
 
         let mut target = display.draw();
 
@@ -591,40 +730,6 @@ fn run() -> Result<()> {
 
         gstate = match gstate {
             State::Game => {
-
-                let mut force_x = 0.0;
-                let mut force_y = 0.0;
-                let mut force_z = 0.0;
-
-                let force_mag = if last_t >= force_mag_end {
-                    10.0 * 2.0
-                } else {
-                    31.4 * 2.0
-                };
-
-                if input.jump && allow_jump && *on_ground.borrow() {
-                    force_y = 3.14 * GRAVITY * force_mag;
-                    times_jumped += 1;
-                    mixer.play(&jump_sound, (1.0 / (times_jumped as f32),))
-                        .chain_err(|| "failed to play jump sound")?;
-                    allow_jump = false;
-                } else if !input.jump {
-                    allow_jump = true;
-                }
-
-                if input.reset_camera {
-                    camera.yaw = 0.0;
-                    camera.pitch = 0.0;
-                }
-
-                if input.stop {
-                    player.borrow_mut().set_linear_velocity(na::zero());
-                }
-
-                camera.yaw += input.camera.x / 10.0;
-                camera.pitch += input.camera.y / 10.0;
-                camera.pitch = na::clamp(camera.pitch, -PI / 2.0, PI / 2.0);
-
                 if curr_t - last_particle > 0.15 {
                     last_particle = curr_t;
 
@@ -641,124 +746,143 @@ fn run() -> Result<()> {
                     });
                 }
 
-                {
-                    let mut pp = new_particles.borrow_mut();
-                    for part in pp.drain(..) {
-                        particles.add(part);
-                    }
-                }
-
                 // Step the world
                 let player_position = player.borrow_mut().get_position();
                 if player_position.y < -300.0 && endtime == 0 {
                     endtime = sdl_timer.ticks();
                     mixer.play(&*end_sound, ()).chain_err(|| "failed to play end sound")?;
                 }
-                *on_ground.borrow_mut() = false;
-                world.borrow_mut().step(dt,
-                                        player_position,
-                                        input.action,
-                                        (settings.get_f32("heightaction_power"),
-                                         settings.get_f32("heightaction_damp"),
-                                         settings.get_f32("heightaction_sin")));
-                particles.step(dt);
+                world.borrow_mut().set_terrain_focus(player_position);
+                let events = world.borrow_mut().step(dt, |w: &mut world::World| -> Result<()> {
+                    let input = input_state.tick_input()?;
 
-                for &body_id in del_diamonds.borrow().iter() {
-                    let mut w = world.borrow_mut();
-                    {
-                        let body = w.bodies().iter().find(|&x| x.borrow().id == body_id).unwrap();
-                        if let Some(idx) = body.borrow().collide_sound {
-                            mixer.play(&*diamond_sounds[idx], ())
-                                .chain_err(|| "failed to play diamond sound")?;
+                    let lift_touched = w.tick_contacts().iter().any(|c| {
+                        c.phase == world::ContactPhase::Begin &&
+                        c.other(player_handle) == Some(lift_body)
+                    });
+                    if lift_touched {
+                        w.platform_mut(lift_platform).trigger();
+                    }
+
+                    // pickups touched during the previous tick
+                    let picked = w.tick_sensors()
+                        .iter()
+                        .filter(|e| {
+                            e.phase == world::SensorPhase::Enter && e.body == player_handle &&
+                            diamonds.contains(&e.sensor)
+                        })
+                        .map(|e| e.sensor)
+                        .collect::<Vec<_>>();
+                    for handle in picked {
+                        {
+                            let body = w.body(handle).unwrap().clone();
+                            if let Some(idx) = body.borrow().collide_sound {
+                                mixer.play(&*diamond_sounds[idx], ())
+                                    .chain_err(|| "failed to play diamond sound")?;
+                            }
+                            if pups.contains(&handle) {
+                                controller.boost(w);
+                                let position = body.borrow().get_position();
+                                w.impulse(position, 1.0);
+                            } else {
+                                // normal prize diamond
+                                // TODO enum these
+                                diams_got += 1;
+                            }
                         }
-                        if pups.contains(&body.borrow().id) {
-                            force_mag_end = sdl_timer.ticks() + force_mag_duration;
-                        } else {
-                            // normal prize diamond
-                            // TODO enum these
-                            diams_got += 1;
+                        w.del_body(handle);
+                        diamonds.retain(|&x| x != handle);
+                        // the gear holds the last reference to the body
+                        diamgears.retain(|g| g.body1.borrow().handle != handle);
+                        if diams_got == diams_tot {
+                            endtime = sdl_timer.ticks();
+                            let idx = if endtime % 1000 > 500 { 1 } else { 0 }; // random, lol
+                            mixer.play(&*win_sounds[idx], ()).chain_err(|| "failed to play win sound")?;
                         }
                     }
-                    w.del_body(body_id);
-                    diamonds.borrow_mut().retain(|&x| x != body_id);
-                    if diams_got == diams_tot {
-                        endtime = sdl_timer.ticks();
-                        let idx = if endtime % 1000 > 500 { 1 } else { 0 }; // random, lol
-                        mixer.play(&*win_sounds[idx], ()).chain_err(|| "failed to play win sound")?;
+
+                    if input.reset_camera {
+                        camera.yaw = 0.0;
+                        camera.pitch = 0.0;
                     }
-                }
-                del_diamonds.borrow_mut().clear();
 
-                let camera_rot = Rotation3::new(Vec3::new(camera.pitch, 0.0, 0.0)) *
-                                 Rotation3::new(Vec3::new(0.0, camera.yaw, 0.0));
-                let camera_pos = player.borrow_mut().get_position() +
-                                 Vec3::new(0.0, 3.0, 5.0) * 2.0 * camera_rot;
+                    camera.yaw += input.camera.x / 10.0;
+                    camera.pitch += input.camera.y / 10.0;
+                    camera.pitch = na::clamp(camera.pitch, -PI / 2.0, PI / 2.0);
 
-                let zfar = 5000.0f32;
-                let znear_default = 0.01f32;
-                // FIXME
-                let znear = if true {
-                    znear_default
-                } else {
-                    let cam = camera_pos.to_point();
-                    let ball = player.borrow_mut().get_position().to_point();
-                    let cam_to_ball = (ball - cam).normalize();
-                    let maxdep = (ball - cam).norm() - 1.0; // radius
-
-                    // welp. doesn't return the closest first.
-                    // TODO: put the ground in its own space maybe
-                    let maxhits = 100usize;
-
-                    let mut contacts = Vec::<ode::dContactGeom>::with_capacity(maxhits);
-                    unsafe {
-                        let ray = ode::dCreateRay(std::ptr::null_mut(), zfar as f64);
-                        ode::dGeomRaySet(ray,
-                                         cam.x as f64,
-                                         cam.y as f64,
-                                         cam.z as f64,
-                                         cam_to_ball.x as f64,
-                                         cam_to_ball.y as f64,
-                                         cam_to_ball.z as f64);
-                        let found = ode::dCollide(ray,
-                                          world.borrow_mut().ode_space() as ode::dGeomID,
-                                          maxhits as i32,
-                                          contacts.as_mut_ptr(),
-                                          std::mem::size_of::<ode::dContactGeom>() as i32) as
-                            usize;
-                        contacts.set_len(found);
-                        ode::dGeomDestroy(ray);
-                    }
+                    fov = (fov + input.zoom).max(PI / 8.0).min(7.0 / 8.0 * PI);
 
-                    let eps = 0.001;
+                    if controller.tick(w, &input, camera.yaw) {
+                        times_jumped += 1;
+                        mixer.play(&jump_sound, (1.0 / (times_jumped as f32),))
+                            .chain_err(|| "failed to play jump sound")?;
+                    }
 
-                    let mut dep = maxdep + eps;
-                    for c in contacts {
-                        if (c.depth as f32) < dep {
-                            dep = c.depth as f32;
+                    // the player leaves a wake behind when moving fast, or on demand
+                    {
+                        let mut player = player.borrow_mut();
+                        let position = player.get_position();
+                        let velocity = player.get_linear_velocity();
+                        let amplitude = Vec3::new(velocity.x, 0.0, velocity.z).norm() / 200.0;
+                        if input.action || amplitude > 0.03 {
+                            w.impulse(position - velocity.normalize() * 8.0, amplitude - 0.03);
                         }
                     }
-                    // closer than depth to the player ball surface? cut everything to be able to see when
-                    // camera goes inside walls or other objects
-                    if dep < maxdep - eps {
-                        dep
-                    } else {
-                        znear_default
-                    }
-                };
 
-                // if *on_ground.borrow() {
-                force_x += force_mag * input.player.x;
-                force_z += force_mag * input.player.y;
-                // }
+                    Ok(())
+                })?;
 
-                player.borrow_mut().add_force(Vec3::new(0.0, force_y, 0.0) * camera_rot);
+                for contact in events.contacts.iter() {
+                    if contact.phase == world::ContactPhase::End ||
+                       !contact.involves(player_handle) {
+                        continue;
+                    }
+                    let volume = (0.04 * vol_scale * contact.normal_velocity *
+                                  contact.normal_velocity)
+                        .min(1.0);
+                    // TODO: multiple different sounds for even more dramatic collisions
+                    if volume > 0.02 {
+                        mixer.play(&*hit_sound, (volume,))
+                            .chain_err(|| "failed to play hit sound")?;
 
-                // impulse based:
-                player.borrow_mut().add_force(Vec3::new(force_x, 0.0, force_z) * 1.0 * camera_rot);
-                // angular momentum based control:
-                player.borrow_mut().add_torque(Vec3::new(force_z, 0.0, -force_x) * camera_rot);
+                        let position = player.borrow().get_position().to_point();
+                        for a in 0..10 {
+                            let angle = (a as f32 / 10.0) * 2.0 * 3.1416;
+                            particles.add(particle::Particle {
+                                position: position + Vec3::new(0.0, -1.0, 0.0),
+                                scale: Vec2::new(volume, volume) * 2.5,
+                                velocity: Vec3::new(angle.cos() * 3.0, 0.2, angle.sin() * 3.0),
+                                lifetime: Some(0.75),
+                                texture: 0,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+                particles.step(dt);
+
+                let camera_rot = Rotation3::new(Vec3::new(camera.pitch, 0.0, 0.0)) *
+                                 Rotation3::new(Vec3::new(0.0, camera.yaw, 0.0));
+                // draw everything partway to the next tick so that the motion doesn't stutter
+                let alpha = world.borrow().interpolation_alpha();
+                let player_pos = player.borrow().get_interpolated_position(alpha);
+                let camera_pos = player_pos + Vec3::new(0.0, 3.0, 5.0) * 2.0 * camera_rot;
 
-                fov = (fov + input.zoom).max(PI / 8.0).min(7.0 / 8.0 * PI);
+                let zfar = 5000.0f32;
+                let znear_default = 0.01f32;
+                // something between the camera and the player? cut everything in front of it to
+                // be able to see when the camera goes inside walls or other objects
+                let znear = {
+                    let cam_to_ball = player_pos - camera_pos;
+                    let maxdep = cam_to_ball.norm() - 1.0; // radius
+                    let eps = 0.001;
+                    let hits = world.borrow()
+                        .raycast(camera_pos, cam_to_ball, maxdep, !body::BODY_CATEGORY_PLAYER_BIT);
+                    match hits.first() {
+                        Some(hit) if hit.distance < maxdep - eps => hit.distance.max(znear_default),
+                        _ => znear_default,
+                    }
+                };
 
                 let projection = na::Perspective3::new(display_width as f32 /
                                                        display_height as f32,
@@ -784,10 +908,8 @@ fn run() -> Result<()> {
                           false)
                     .chain_err(|| "failed to draw cubemap")?;
 
-                let player_pos = player.borrow_mut().get_position();
-
                 for body in world.borrow().bodies() {
-                    let model = body.borrow_mut().get_posrot_homogeneous();
+                    let model = body.borrow().get_interpolated_posrot_homogeneous(alpha);
                     let modelview = cam_view * model;
 
                     let b = body.borrow_mut();
@@ -822,6 +944,15 @@ fn run() -> Result<()> {
                     }
                 }
 
+                if debug_draw.enabled {
+                    debug_draw.add_world(&world.borrow(), player_pos);
+                    for gear in diamgears.iter() {
+                        debug_draw.add_joint(gear);
+                    }
+                }
+                debug_draw.draw(&display, &mut target, *projection.as_ref(), *cam_view.as_ref())
+                    .chain_err(|| "failed to draw physics debug lines")?;
+
                 particles.draw(&mut target, *projection.as_ref(), *cam_view.as_ref())
                     .chain_err(|| "failed to render particles")?;
 
@@ -858,6 +989,9 @@ fn run() -> Result<()> {
 
             }
             State::Menu(sel) => {
+                // no ticks here to look around with
+                input_state.discard_pending();
+
                 nanovg.begin_frame(800, 600, 1.0);
 
                 nanovg.begin_path();
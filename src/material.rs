@@ -0,0 +1,151 @@
+use body::BodyConfig;
+use ode;
+
+use std::collections::HashMap;
+
+// Named surface kinds for bodies and terrain regions. Default means the surface is described by
+// the friction and restitution in the BodyConfig.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Material {
+    Default,
+    Grass,
+    Ice,
+    Rubber,
+    Bumper,
+}
+
+// What a contact between two materials feels like
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceParams {
+    pub friction: f32,
+    pub bounce: f32,
+    pub bounce_velocity: f32, // minimum incoming velocity for a bounce
+    pub rolling_friction: f32, // 0 for none
+    pub softness: f32, // constraint force mixing, 0 for a hard contact
+}
+
+impl SurfaceParams {
+    pub fn new(friction: f32, bounce: f32) -> Self {
+        SurfaceParams {
+            friction: friction,
+            bounce: bounce,
+            bounce_velocity: 0.1,
+            rolling_friction: 0.0,
+            softness: 0.0,
+        }
+    }
+
+    fn from_config(config: &BodyConfig) -> Self {
+        SurfaceParams::new(config.friction, config.restitution)
+    }
+
+    // slippery wins over grippy and bouncy over dead
+    fn combine(&self, other: &SurfaceParams) -> Self {
+        SurfaceParams {
+            friction: self.friction.min(other.friction),
+            bounce: self.bounce.max(other.bounce),
+            bounce_velocity: self.bounce_velocity.min(other.bounce_velocity),
+            rolling_friction: self.rolling_friction.max(other.rolling_friction),
+            softness: self.softness.max(other.softness),
+        }
+    }
+
+    pub fn apply(&self, surface: &mut ode::dSurfaceParameters) {
+        surface.mode = 0;
+        surface.mu = self.friction as f64;
+
+        if self.bounce > 0.0 {
+            surface.mode |= ode::dContactBounce as i32;
+            surface.bounce = self.bounce as f64;
+            surface.bounce_vel = self.bounce_velocity as f64;
+        }
+
+        if self.rolling_friction > 0.0 {
+            // same resistance in both directions, none for spinning about the normal
+            surface.mode |= ode::dContactRolling as i32;
+            surface.rho = self.rolling_friction as f64;
+            surface.rho2 = self.rolling_friction as f64;
+            surface.rhoN = 0.0;
+        }
+
+        if self.softness > 0.0 {
+            surface.mode |= ode::dContactSoftCFM as i32;
+            surface.soft_cfm = self.softness as f64;
+        }
+    }
+}
+
+// Resolves the surface parameters for a pair of materials. Pairs not found in the table are
+// combined from each side: named materials from their entry against Default, and Default from
+// the body config. An entry is for bodies with the default config friction; the friction of a
+// Default side body scales it, so that a config can still make a body grippier or slipperier
+// than usual on a named surface.
+pub struct MaterialTable {
+    pairs: HashMap<(Material, Material), SurfaceParams>,
+}
+
+impl MaterialTable {
+    pub fn new() -> Self {
+        MaterialTable { pairs: HashMap::new() }
+    }
+
+    pub fn set(&mut self, a: Material, b: Material, params: SurfaceParams) {
+        self.pairs.insert(MaterialTable::key(a, b), params);
+    }
+
+    pub fn get(&self, a: Material, b: Material) -> Option<&SurfaceParams> {
+        self.pairs.get(&MaterialTable::key(a, b))
+    }
+
+    pub fn resolve(&self,
+                   a: Material,
+                   a_config: &BodyConfig,
+                   b: Material,
+                   b_config: &BodyConfig)
+                   -> SurfaceParams {
+        if let Some(params) = self.get(a, b) {
+            let mut params = *params;
+            let usual = BodyConfig::default().friction;
+            for &(m, config) in &[(a, a_config), (b, b_config)] {
+                if m == Material::Default {
+                    params.friction *= config.friction / usual;
+                }
+            }
+            return params;
+        }
+
+        let side = |m: Material, config: &BodyConfig| if m == Material::Default {
+            SurfaceParams::from_config(config)
+        } else {
+            self.get(m, Material::Default)
+                .cloned()
+                .unwrap_or_else(|| SurfaceParams::from_config(config))
+        };
+
+        side(a, a_config).combine(&side(b, b_config))
+    }
+
+    fn key(a: Material, b: Material) -> (Material, Material) {
+        if a <= b { (a, b) } else { (b, a) }
+    }
+}
+
+impl Default for MaterialTable {
+    fn default() -> Self {
+        use self::Material::*;
+
+        let mut table = MaterialTable::new();
+
+        // grass is what the whole level used to be, very grippy so that the ball rolls
+        table.set(Grass, Default, SurfaceParams::new(50.0, 0.0));
+        table.set(Ice, Default, SurfaceParams::new(0.05, 0.0));
+        table.set(Rubber,
+                  Default,
+                  SurfaceParams { rolling_friction: 0.02, ..SurfaceParams::new(50.0, 0.6) });
+        table.set(Bumper,
+                  Default,
+                  SurfaceParams { softness: 0.001, ..SurfaceParams::new(5.0, 1.2) });
+
+        table
+    }
+}
@@ -87,31 +87,25 @@ impl Mesh {
         Mesh::new(f, positions, normals, texcoord, retain)
     }
 
-    pub fn from_texture<F: Facade>(f: &F,
-                                   texture: &glium::texture::RawImage2d<'static, u8>,
-                                   scale: f32)
-                                   -> (Result<Mesh>, (i32, i32), Vec<f32>, Vec<usize>) {
+    // build the landscape mesh for the size samples of a heightfield starting from min, centered
+    // at the middle of that part; the texture spans the whole heightfield. also returns the
+    // heightfield index of each vertex so that the mesh can be deformed later on
+    pub fn from_heightfield<F: Facade>(f: &F,
+                                       resolution: (i32, i32),
+                                       heightfield: &[f32],
+                                       scale: f32,
+                                       min: (i32, i32),
+                                       size: (i32, i32))
+                                       -> Result<(Mesh, Vec<usize>)> {
         let retain = true;
-        let (width, depth) = (texture.width as i32, texture.height as i32);
-        let mut heightfield = Vec::new();
-        heightfield.resize((width * depth) as usize, 0.0);
+        let (width, depth) = resolution;
         let mut positions = Vec::new();
         let mut normals = Vec::new();
         let mut texture_coordinates = Vec::new();
         let mut idx = Vec::new();
 
-        // read texture to heightfield
-        for x in 0..width {
-            for z in 0..depth {
-                let hmp = (z * width + x) as usize;
-                let h = texture.data[hmp * 4] as f32 / 256.0 * 32.0 * scale;
-                let h = if h < 1.0 { -400.0 } else { h };
-                heightfield[hmp] = h;
-            }
-        }
-
-        for x in 0..width - 1 {
-            for z in 0..depth - 1 {
+        for x in min.0..min.0 + size.0 - 1 {
+            for z in min.1..min.1 + size.1 - 1 {
                 let hmp = (z * width + x) as usize;
 
                 let tx = x as f32 / width as f32;
@@ -120,8 +114,8 @@ impl Mesh {
                 let ts = 1.0f32 / width as f32;
                 let s = scale;
 
-                let px = x as f32 * scale;
-                let pz = z as f32 * scale;
+                let px = (x - min.0) as f32 * scale;
+                let pz = (z - min.1) as f32 * scale;
 
                 // let st = width as u32;
                 positions.push(Pnt3::new(px, heightfield[hmp] as f32, pz));
@@ -158,14 +152,46 @@ impl Mesh {
 
         // offset the mesh
         for vec in positions.iter_mut() {
-            vec.x -= scale * 0.5 * (width - 1) as f32;
-            vec.z -= scale * 0.5 * (depth - 1) as f32;
+            vec.x -= scale * 0.5 * (size.0 - 1) as f32;
+            vec.z -= scale * 0.5 * (size.1 - 1) as f32;
         }
 
-        (Mesh::new(f, positions, normals, texture_coordinates, retain),
-         (width, depth),
-         heightfield,
-         idx)
+        Ok((Mesh::new(f, positions, normals, texture_coordinates, retain)?, idx))
+    }
+
+    // a box centered at the origin, each face textured with the whole texture
+    pub fn for_box<F: Facade>(f: &F, size: Vec3) -> Result<Mesh> {
+        let half = [size.x * 0.5, size.y * 0.5, size.z * 0.5];
+        let mut positions = Vec::with_capacity(36);
+        let mut normals = Vec::with_capacity(36);
+        let mut uvs = Vec::with_capacity(36);
+
+        for axis in 0..3 {
+            // u cross v points along the axis
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for &sign in &[1.0f32, -1.0] {
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                // counterclockwise when seen from outside
+                let corners: &[(f32, f32)] = if sign > 0.0 {
+                    &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                } else {
+                    &[(-1.0, -1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+                };
+                for &(cu, cv) in corners {
+                    let mut p = [0.0; 3];
+                    p[axis] = sign * half[axis];
+                    p[u] = cu * half[u];
+                    p[v] = cv * half[v];
+                    positions.push(Pnt3::new(p[0], p[1], p[2]));
+                    normals.push(Vec3::new(normal[0], normal[1], normal[2]));
+                    uvs.push(Pnt3::new((cu + 1.0) * 0.5, (cv + 1.0) * 0.5, 0.0));
+                }
+            }
+        }
+
+        let retain = false;
+        Mesh::new(f, positions, normals, uvs, retain)
     }
 
     pub fn for_cubemap<F: Facade>(f: &F) -> Result<Mesh> {
@@ -0,0 +1,161 @@
+use body::BodyHandle;
+use math::*;
+use na::Norm;
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathMode {
+    // around and around; a waypoint path closes back to its first point
+    Loop,
+    // back and forth between the ends
+    PingPong,
+    // waits at an end until triggered, then moves to the other end
+    Triggered,
+}
+
+#[derive(Debug, Clone)]
+pub enum PlatformMotion {
+    // through the points at speed world units per second
+    Waypoints { points: Vec<Vec3>, speed: f32 },
+    // about an axis through the body origin at speed radians per second, between zero and
+    // angle; a loop keeps going around
+    Rotation { axis: Vec3, angle: f32, speed: f32 },
+}
+
+// Moves a fixed (kinematic) body; World updates these every tick
+#[derive(Debug, Clone)]
+pub struct Platform {
+    pub body: BodyHandle,
+    pub motion: PlatformMotion,
+    pub mode: PathMode,
+    // orientation at angle zero, in ODE's w, x, y, z order; World takes it from the body
+    pub base_quaternion: [f64; 4],
+    // distance along the path or angle of the rotation
+    progress: f32,
+    // 1 forwards, -1 backwards, 0 stopped
+    direction: f32,
+}
+
+impl Platform {
+    pub fn new(body: BodyHandle, motion: PlatformMotion, mode: PathMode) -> Platform {
+        Platform {
+            body: body,
+            motion: motion,
+            mode: mode,
+            base_quaternion: [1.0, 0.0, 0.0, 0.0],
+            progress: 0.0,
+            direction: if mode == PathMode::Triggered { 0.0 } else { 1.0 },
+        }
+    }
+
+    // total length of the path or the rotation
+    fn length(&self) -> f32 {
+        match self.motion {
+            PlatformMotion::Waypoints { ref points, .. } => {
+                let mut len = 0.0;
+                for w in points.windows(2) {
+                    len += (w[1] - w[0]).norm();
+                }
+                if self.mode == PathMode::Loop && points.len() > 1 {
+                    len += (points[0] - points[points.len() - 1]).norm();
+                }
+                len
+            }
+            PlatformMotion::Rotation { angle, .. } => {
+                if self.mode == PathMode::Loop {
+                    2.0 * ::std::f32::consts::PI
+                } else {
+                    angle
+                }
+            }
+        }
+    }
+
+    fn speed(&self) -> f32 {
+        match self.motion {
+            PlatformMotion::Waypoints { speed, .. } => speed,
+            PlatformMotion::Rotation { speed, .. } => speed,
+        }
+    }
+
+    // Start a triggered platform towards the end it's not at; no effect while it's moving
+    pub fn trigger(&mut self) {
+        if self.direction == 0.0 {
+            self.direction = if self.progress <= 0.0 { 1.0 } else { -1.0 };
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_moving(&self) -> bool {
+        self.direction != 0.0
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        let len = self.length();
+        if len <= 0.0 {
+            return;
+        }
+        self.progress += self.direction * self.speed() * dt;
+
+        match self.mode {
+            PathMode::Loop => {
+                self.progress %= len;
+                if self.progress < 0.0 {
+                    self.progress += len;
+                }
+            }
+            PathMode::PingPong => {
+                if self.progress > len {
+                    self.progress = 2.0 * len - self.progress;
+                    self.direction = -1.0;
+                } else if self.progress < 0.0 {
+                    self.progress = -self.progress;
+                    self.direction = 1.0;
+                }
+            }
+            PathMode::Triggered => {
+                if self.progress >= len {
+                    self.progress = len;
+                    self.direction = 0.0;
+                } else if self.progress <= 0.0 {
+                    self.progress = 0.0;
+                    self.direction = 0.0;
+                }
+            }
+        }
+    }
+
+    // Where a waypoint platform is now
+    pub fn position(&self) -> Option<Vec3> {
+        let points = match self.motion {
+            PlatformMotion::Waypoints { ref points, .. } => points,
+            PlatformMotion::Rotation { .. } => return None,
+        };
+        if points.is_empty() {
+            return None;
+        }
+
+        let closing = if self.mode == PathMode::Loop {
+            Some((points[points.len() - 1], points[0]))
+        } else {
+            None
+        };
+        let mut left = self.progress;
+        for (a, b) in points.windows(2).map(|w| (w[0], w[1])).chain(closing) {
+            let seg = (b - a).norm();
+            if left <= seg && seg > 0.0 {
+                return Some(a + (b - a) * (left / seg));
+            }
+            left -= seg;
+        }
+        Some(if closing.is_some() { points[0] } else { points[points.len() - 1] })
+    }
+
+    // The axis and the angle of a rotating platform now
+    pub fn rotation(&self) -> Option<(Vec3, f32)> {
+        match self.motion {
+            PlatformMotion::Rotation { axis, .. } => Some((axis.normalize(), self.progress)),
+            PlatformMotion::Waypoints { .. } => None,
+        }
+    }
+}
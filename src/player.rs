@@ -0,0 +1,349 @@
+// Rolls the player ball around by the input, relative to where the camera looks
+
+use body::BodyHandle;
+use input::Input;
+use math::*;
+use na;
+use na::{Norm, Rotation3};
+use std;
+use world::{World, ContactPhase};
+
+#[derive(Debug, Copy, Clone)]
+pub struct PlayerParams {
+    // push of a full stick, normally and while boosted
+    pub move_force: f32,
+    pub boost_force: f32,
+    // seconds that a boost lasts
+    pub boost_duration: f32,
+    // the jump push per unit of move push
+    pub jump_scale: f32,
+    // fraction of the move push that works in the air
+    pub air_control: f32,
+    // seconds that a jump pressed before landing waits to happen
+    pub jump_buffer: f32,
+    // seconds after leaving the ground that a jump still works
+    pub coyote_time: f32,
+    // horizontal speed above which the stick only steers and brakes
+    pub max_speed: f32,
+}
+
+impl Default for PlayerParams {
+    fn default() -> PlayerParams {
+        PlayerParams {
+            move_force: 20.0,
+            boost_force: 62.8,
+            boost_duration: 10.0,
+            jump_scale: 3.14 * GRAVITY,
+            air_control: 1.0,
+            jump_buffer: 0.0,
+            coyote_time: 0.0,
+            max_speed: std::f32::INFINITY,
+        }
+    }
+}
+
+// Keeps what it needs between ticks; call tick() once per physics tick before the world steps
+pub struct PlayerController {
+    pub params: PlayerParams,
+    body: BodyHandle,
+    on_ground: bool,
+    // seconds since the ball last touched the ground
+    air_time: f32,
+    // seconds left for a pressed jump to happen
+    queued_jump: Option<f32>,
+    // the jump button must be let go between jumps
+    jump_released: bool,
+    // the contacts of a jump's tick are from before the ball took off
+    just_jumped: bool,
+    // in simulated seconds so that replays get the same boost
+    boost_end: f32,
+}
+
+impl PlayerController {
+    pub fn new(body: BodyHandle, params: PlayerParams) -> PlayerController {
+        PlayerController {
+            params: params,
+            body: body,
+            on_ground: false,
+            air_time: std::f32::INFINITY,
+            queued_jump: None,
+            jump_released: true,
+            just_jumped: false,
+            boost_end: 0.0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    // Push harder for a while, starting now
+    pub fn boost(&mut self, world: &World) {
+        self.boost_end = world.time() + self.params.boost_duration;
+    }
+
+    // Push the ball by this tick's input, with yaw as the camera's turn about the vertical axis.
+    // Returns whether the ball jumped.
+    pub fn tick(&mut self, world: &World, input: &Input, yaw: f32) -> bool {
+        let body = match world.body(self.body) {
+            Some(body) => body,
+            None => return false,
+        };
+        let mut body = body.borrow_mut();
+
+        // the contacts of the previous tick
+        let handle = self.body;
+        let touching = world.tick_contacts().iter().any(|c| {
+            c.phase != ContactPhase::End && c.other(handle).map_or(false, |b| world.is_terrain(b))
+        });
+        self.on_ground = touching && !self.just_jumped;
+        self.just_jumped = false;
+        if self.on_ground {
+            self.air_time = 0.0;
+        } else {
+            self.air_time += PHYS_DT;
+        }
+
+        if input.jump && self.jump_released {
+            self.queued_jump = Some(self.params.jump_buffer);
+            self.jump_released = false;
+        } else if !input.jump {
+            self.jump_released = true;
+        }
+
+        if input.stop {
+            body.set_linear_velocity(na::zero());
+        }
+
+        let force = if world.time() >= self.boost_end {
+            self.params.move_force
+        } else {
+            self.params.boost_force
+        };
+        let control = if self.on_ground { 1.0 } else { self.params.air_control };
+
+        // only the turn, so that looking up or down doesn't tilt the push or the jump
+        let rot = Rotation3::new(Vec3::new(0.0, yaw, 0.0));
+        let mut push = Vec3::new(input.player.x, 0.0, input.player.y) * (force * control) * rot;
+
+        // past the top speed, don't push any further forwards
+        let velocity = body.get_linear_velocity();
+        let horizontal = Vec3::new(velocity.x, 0.0, velocity.z);
+        let speed = horizontal.norm();
+        if speed >= self.params.max_speed {
+            let dir = horizontal / speed;
+            let forwards = na::dot(&push, &dir);
+            if forwards > 0.0 {
+                push = push - dir * forwards;
+            }
+        }
+
+        let mut jumped = false;
+        if let Some(left) = self.queued_jump {
+            if self.air_time <= self.params.coyote_time {
+                body.add_force(Vec3::new(0.0, self.params.jump_scale * force, 0.0));
+                self.queued_jump = None;
+                // no second jump off the same ground
+                self.air_time = std::f32::INFINITY;
+                self.just_jumped = true;
+                jumped = true;
+            } else if left <= 0.0 {
+                self.queued_jump = None;
+            } else {
+                self.queued_jump = Some(left - PHYS_DT);
+            }
+        }
+
+        // pushing moves the ball through the air too, while rolling works on the ground
+        body.add_force(push);
+        body.add_torque(Vec3::new(push.z, 0.0, -push.x));
+
+        jumped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use body::Body;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use world::tests::{flat_world, add_ball};
+
+    fn tick(world: &mut World, controller: &mut PlayerController, input: &Input) -> bool {
+        let mut jumped = false;
+        world.step(PHYS_DT, |w| {
+                jumped = controller.tick(w, input, 0.0);
+                Ok(())
+            })
+            .unwrap();
+        jumped
+    }
+
+    fn jump(pressed: bool) -> Input {
+        Input { jump: pressed, ..Default::default() }
+    }
+
+    fn stick(x: f32, y: f32) -> Input {
+        Input { player: Vec2::new(x, y), ..Default::default() }
+    }
+
+    // Jumps that forgive pressing a little early or late
+    fn forgiving() -> PlayerParams {
+        PlayerParams { jump_buffer: 0.1, coyote_time: 0.1, ..Default::default() }
+    }
+
+    // A ball resting on the ground, with a controller that has seen it there
+    fn grounded(world: &mut World,
+                params: PlayerParams)
+                -> (Rc<RefCell<Body>>, PlayerController) {
+        let ball = add_ball(world, Vec3::new(0.0, 1.0, 0.0));
+        let mut controller = PlayerController::new(ball.borrow().handle, params);
+        for _ in 0..50 {
+            tick(world, &mut controller, &Input::default());
+        }
+        assert!(controller.on_ground());
+        (ball, controller)
+    }
+
+    // A ball high up in the air, moving at velocity
+    fn flying(world: &mut World,
+              x: f32,
+              velocity: Vec3,
+              params: PlayerParams)
+              -> (Rc<RefCell<Body>>, PlayerController) {
+        let ball = add_ball(world, Vec3::new(x, 20.0, 0.0));
+        ball.borrow_mut().set_linear_velocity(velocity);
+        let controller = PlayerController::new(ball.borrow().handle, params);
+        (ball, controller)
+    }
+
+    // The tick on which a ball dropped from a little height first finds the ground
+    fn landing_tick() -> usize {
+        let mut world = flat_world();
+        let ball = add_ball(&mut world, Vec3::new(0.0, 3.0, 0.0));
+        let mut controller = PlayerController::new(ball.borrow().handle, forgiving());
+        (1..200)
+            .find(|_| {
+                tick(&mut world, &mut controller, &Input::default());
+                controller.on_ground()
+            })
+            .unwrap()
+    }
+
+    // Press jump before the given tick and keep holding it; returns the tick of the jump
+    fn drop_and_jump(press: usize) -> Option<usize> {
+        let mut world = flat_world();
+        let ball = add_ball(&mut world, Vec3::new(0.0, 3.0, 0.0));
+        let mut controller = PlayerController::new(ball.borrow().handle, forgiving());
+        (1..press + 30).find(|&t| tick(&mut world, &mut controller, &jump(t >= press)))
+    }
+
+    #[test]
+    fn jump_pressed_just_before_landing_waits_for_the_ground() {
+        let landing = landing_tick();
+        // the buffer is ten ticks
+        let jumped = drop_and_jump(landing - 5).expect("never jumped");
+        assert!(jumped >= landing && jumped <= landing + 1, "jumped at {} of {}", jumped, landing);
+        assert_eq!(drop_and_jump(landing - 15), None);
+    }
+
+    #[test]
+    fn jump_works_a_moment_after_leaving_the_ground() {
+        for &(ticks, works) in &[(5, true), (20, false)] {
+            let mut world = flat_world();
+            let (ball, mut controller) = grounded(&mut world, forgiving());
+            {
+                let mut ball = ball.borrow_mut();
+                ball.set_position(Vec3::new(0.0, 10.0, 0.0));
+                ball.set_linear_velocity(na::zero());
+            }
+            for _ in 0..ticks {
+                tick(&mut world, &mut controller, &Input::default());
+            }
+            let jumped = (0..5).any(|_| tick(&mut world, &mut controller, &jump(true)));
+            assert_eq!(jumped, works, "{} ticks in the air", ticks);
+        }
+    }
+
+    #[test]
+    fn no_second_jump_in_the_air() {
+        let mut world = flat_world();
+        let (_ball, mut controller) = grounded(&mut world, forgiving());
+        assert!(tick(&mut world, &mut controller, &jump(true)));
+        // let go and press again right away, while the ball still rises
+        assert!(!tick(&mut world, &mut controller, &jump(false)));
+        for _ in 0..20 {
+            assert!(!tick(&mut world, &mut controller, &jump(true)));
+        }
+        assert!(!controller.on_ground());
+    }
+
+    #[test]
+    fn air_control_scales_the_push_in_the_air() {
+        let mut world = flat_world();
+        let params = PlayerParams { air_control: 0.25, ..Default::default() };
+        let (full_ball, mut full) = flying(&mut world, -10.0, na::zero(), Default::default());
+        let (weak_ball, mut weak) = flying(&mut world, 10.0, na::zero(), params);
+        for _ in 0..10 {
+            world.step(PHYS_DT, |w| {
+                    full.tick(w, &stick(1.0, 0.0), 0.0);
+                    weak.tick(w, &stick(1.0, 0.0), 0.0);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        let full_speed = full_ball.borrow_mut().get_linear_velocity().x;
+        let weak_speed = weak_ball.borrow_mut().get_linear_velocity().x;
+        assert!(full_speed > 0.0);
+        assert!((weak_speed / full_speed - 0.25).abs() < 0.02,
+                "{} against {}",
+                weak_speed,
+                full_speed);
+    }
+
+    #[test]
+    fn max_speed_only_steers_and_brakes() {
+        let mut world = flat_world();
+        let params = PlayerParams { max_speed: 5.0, ..Default::default() };
+        let (ball, mut controller) = flying(&mut world, 0.0, Vec3::new(10.0, 0.0, 0.0), params);
+        let velocity = || ball.borrow_mut().get_linear_velocity();
+
+        for _ in 0..10 {
+            tick(&mut world, &mut controller, &stick(1.0, 0.0));
+        }
+        let forwards = velocity();
+        assert!(forwards.x <= 10.0 && forwards.x > 9.5, "at {}", forwards.x);
+
+        tick(&mut world, &mut controller, &stick(0.0, 1.0));
+        assert!(velocity().z > 0.0);
+
+        tick(&mut world, &mut controller, &stick(-1.0, 0.0));
+        assert!(velocity().x < forwards.x - 0.1);
+    }
+
+    #[test]
+    fn boost_runs_out() {
+        let mut world = flat_world();
+        let params = PlayerParams { boost_duration: 0.05, ..Default::default() };
+        let (ball, mut controller) = flying(&mut world, 0.0, na::zero(), params);
+        controller.boost(&world);
+        let mut gain = || {
+            let before = ball.borrow_mut().get_linear_velocity().x;
+            tick(&mut world, &mut controller, &stick(1.0, 0.0));
+            ball.borrow_mut().get_linear_velocity().x - before
+        };
+
+        let boosted = gain();
+        for _ in 0..20 {
+            gain();
+        }
+        let normal = gain();
+        let ratio = params.boost_force / params.move_force;
+        assert!((boosted / normal - ratio).abs() < 0.3,
+                "{} against {}",
+                boosted,
+                normal);
+    }
+}
@@ -0,0 +1,115 @@
+use errors::*;
+use input::Input;
+use settings::Settings;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+// A replay is a plain text file: the level and every setting that was in use, followed by one
+// line of input per physics tick. Floats are printed with {} which round-trips exactly.
+//
+//   level level2.png
+//   setting volume_scale 0.01
+//   tick <jump> <reset_camera> <stop> <action> <camera.x> <camera.y> <player.x> <player.y> <zoom>
+
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(filename: &str, level: &str, settings: &Settings) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(filename).chain_err(|| "cannot create replay file")?);
+
+        writeln!(out, "level {}", level).chain_err(|| "cannot write replay")?;
+        // sorted just so that replays of the same setup diff nicely
+        let mut items = settings.items().collect::<Vec<_>>();
+        items.sort();
+        for (name, values) in items {
+            writeln!(out, "setting {} {}", name, values.join(" ")).chain_err(|| "cannot write replay")?;
+        }
+
+        Ok(Recorder { out: out })
+    }
+
+    pub fn record(&mut self, input: &Input) -> Result<()> {
+        let b = |x: bool| if x { 1 } else { 0 };
+        writeln!(self.out,
+                 "tick {} {} {} {} {} {} {} {} {}",
+                 b(input.jump),
+                 b(input.reset_camera),
+                 b(input.stop),
+                 b(input.action),
+                 input.camera.x,
+                 input.camera.y,
+                 input.player.x,
+                 input.player.y,
+                 input.zoom)
+            .chain_err(|| "cannot write replay")
+    }
+}
+
+pub struct Replay {
+    pub level: String,
+    pub settings: Settings,
+    ticks: Vec<Input>,
+    pos: usize,
+}
+
+impl Replay {
+    pub fn load(filename: &str) -> Result<Self> {
+        let file = File::open(filename).chain_err(|| "cannot open replay file")?;
+
+        let mut level = None;
+        let mut setting_lines = Vec::new();
+        let mut ticks = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = line.chain_err(|| "cannot read replay file")?;
+            let mut tokens = line.splitn(2, ' ');
+            match (tokens.next(), tokens.next()) {
+                (Some("level"), Some(rest)) => level = Some(rest.to_owned()),
+                (Some("setting"), Some(rest)) => setting_lines.push(rest.to_owned()),
+                (Some("tick"), Some(rest)) => ticks.push(parse_tick(rest)?),
+                (Some(""), None) => (),
+                _ => bail!("bad replay line '{}'", line),
+            }
+        }
+
+        Ok(Replay {
+            level: level.ok_or("replay has no level")?,
+            settings: Settings::from_lines(setting_lines.iter().map(|l| &l[..])),
+            ticks: ticks,
+            pos: 0,
+        })
+    }
+
+    // Input for the next tick, or None when the recording has run out
+    pub fn next_input(&mut self) -> Option<Input> {
+        let input = self.ticks.get(self.pos).cloned();
+        self.pos += 1;
+        input
+    }
+}
+
+fn parse_tick(line: &str) -> Result<Input> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    if tokens.len() != 9 {
+        bail!("bad replay tick '{}'", line);
+    }
+
+    let b = |t: &str| t == "1";
+    let f = |t: &str| t.parse::<f32>().chain_err(|| "bad number in replay tick");
+
+    let mut input: Input = Default::default();
+    input.jump = b(tokens[0]);
+    input.reset_camera = b(tokens[1]);
+    input.stop = b(tokens[2]);
+    input.action = b(tokens[3]);
+    input.camera.x = f(tokens[4])?;
+    input.camera.y = f(tokens[5])?;
+    input.player.x = f(tokens[6])?;
+    input.player.y = f(tokens[7])?;
+    input.zoom = f(tokens[8])?;
+
+    Ok(input)
+}
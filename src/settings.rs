@@ -2,21 +2,31 @@ use errors::*;
 use math::*;
 
 use std::collections::HashMap;
+use std::collections::hash_map;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
 // bleh, would include rows: Vec<String> and a HashMap<&str, Vec<&str>> but the refs wouldn't
 // probably work, right?
+#[derive(Clone)]
 pub struct Settings {
     items: HashMap<String, Vec<String>>,
 }
 
 impl Settings {
     pub fn new(filename: &str) -> Result<Self> {
+        let lines = BufReader::new(File::open(filename).chain_err(|| "cannot open file")?)
+            .lines()
+            .map(|line| line.unwrap())
+            .collect::<Vec<_>>();
+
+        Ok(Settings::from_lines(lines.iter().map(|l| &l[..])))
+    }
+
+    pub fn from_lines<'a, I: Iterator<Item = &'a str>>(lines: I) -> Self {
         let mut items: HashMap<String, Vec<String>> = HashMap::new();
 
-        for line in BufReader::new(File::open(filename).chain_err(|| "cannot open file")?).lines() {
-            let line = line.unwrap();
+        for line in lines {
             let line = line.split('#').next().unwrap();
             let tokens = line.split_whitespace().collect::<Vec<_>>();
             if tokens.len() > 0 {
@@ -26,7 +36,15 @@ impl Settings {
             }
         }
 
-        Ok(Settings { items: items })
+        Settings { items: items }
+    }
+
+    pub fn items(&self) -> hash_map::Iter<String, Vec<String>> {
+        self.items.iter()
+    }
+
+    pub fn get_str(&self, name: &str) -> &str {
+        &self.items.get(name).unwrap()[0]
     }
 
     pub fn get_u32(&self, name: &str) -> u32 {
@@ -37,7 +55,7 @@ impl Settings {
         self.items.get(name).unwrap()[0].parse().unwrap()
     }
 
-    pub fn _get_vec3(&self, name: &str) -> Vec3 {
+    pub fn get_vec3(&self, name: &str) -> Vec3 {
         let mut v = self.items.get(name).unwrap().iter().map(|x| x.parse().unwrap());
         Vec3::new(v.next().unwrap(), v.next().unwrap(), v.next().unwrap())
     }
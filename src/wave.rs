@@ -0,0 +1,252 @@
+// Heightfield wave simulation. Every sample has a rest height (origin) that it's pulled back to,
+// and displacements from the rest height spread to the neighbouring samples.
+//
+// The solvers can split the grid to rows on the rayon thread pool. Each pass only reads the
+// arrays that it doesn't write and every sample is computed by the same code either way, so the
+// output is bit-identical to the serial run.
+
+use rayon::prelude::*;
+
+// The arrays are row-major, resolution.0 samples per row
+pub struct WaveGrid<'a> {
+    pub height: &'a mut [f32],
+    pub origin: &'a [f32],
+    pub velocity: &'a mut [f32],
+    pub resolution: (usize, usize),
+    pub spacing: f32, // world units between samples
+}
+
+pub trait WaveSolver {
+    // advance the waves by one physics tick of dt seconds
+    fn step(&mut self, grid: &mut WaveGrid, dt: f32);
+
+    // how many samples away a step of dt can carry a change; stepping a part of a bigger grid
+    // needs this many extra samples around it to give the same result as stepping the whole
+    fn reach(&self, _spacing: f32, _dt: f32) -> usize {
+        1
+    }
+}
+
+// rows per parallel work item, single rows are too little work to be worth a task
+const BAND_ROWS: usize = 16;
+
+// call f(z, row) for each row of a row-major array
+fn for_each_row<T, F>(data: &mut [T], width: usize, parallel: bool, f: F)
+    where T: Send,
+          F: Fn(usize, &mut [T]) + Sync
+{
+    if parallel {
+        data.par_chunks_mut(width * BAND_ROWS).enumerate().for_each(|(band, rows)| {
+            for (z, row) in rows.chunks_mut(width).enumerate() {
+                f(band * BAND_ROWS + z, row);
+            }
+        });
+    } else {
+        for (z, row) in data.chunks_mut(width).enumerate() {
+            f(z, row);
+        }
+    }
+}
+
+// The original model: every sample is a damped spring tied to the average of its neighbours.
+// It runs on its own clock of wave_dt per tick, whatever the physics dt is.
+pub struct SpringSolver {
+    pub coupling: f32,
+    pub damping: f32, // velocity kept per tick
+    pub restore: f32, // pull towards the rest height
+    pub wave_dt: f32,
+    pub parallel: bool,
+}
+
+impl SpringSolver {
+    pub fn new(coupling: f32, damping: f32, restore: f32) -> Self {
+        SpringSolver {
+            coupling: coupling,
+            damping: damping,
+            restore: restore,
+            wave_dt: 0.1,
+            parallel: false,
+        }
+    }
+}
+
+impl Default for SpringSolver {
+    fn default() -> Self {
+        SpringSolver::new(1.0, 0.998, 0.001)
+    }
+}
+
+impl WaveSolver for SpringSolver {
+    fn step(&mut self, grid: &mut WaveGrid, _dt: f32) {
+        let (width, depth) = grid.resolution;
+        let (wave_dt, coupling, damping, restore) =
+            (self.wave_dt, self.coupling, self.damping, self.restore);
+
+        {
+            let (height, origin) = (&*grid.height, grid.origin);
+            let d = |j: usize| height[j] - origin[j];
+
+            for_each_row(grid.velocity, width, self.parallel, |z, velocity| {
+                for x in 0..width {
+                    let i = x + z * width;
+
+                    let neighs = 0.0 + if x > 0 { d(i - 1) } else { 0.0 } +
+                                 if z > 0 { d(i - width) } else { 0.0 } +
+                                 if x + 1 < width { d(i + 1) } else { 0.0 } +
+                                 if z + 1 < depth { d(i + width) } else { 0.0 };
+
+                    let v = velocity[x] * damping;
+                    let v = v + (neighs / 4.0 - d(i)) * wave_dt * coupling;
+                    velocity[x] = v + (origin[i] - height[i]) * wave_dt * restore;
+                }
+            });
+        }
+
+        let velocity = &*grid.velocity;
+        for_each_row(grid.height, width, self.parallel, |z, height| {
+            for x in 0..width {
+                height[x] += velocity[x + z * width] * wave_dt;
+            }
+        });
+    }
+}
+
+// Largest Courant number that a substep may have. The explicit scheme below is stable up to
+// 1/sqrt(2) in 2D; stay a bit under that.
+pub const MAX_COURANT: f32 = 0.5;
+
+// The 2D wave equation, d2h/dt2 = c^2 (d2h/dx2 + d2h/dz2), on the displacement from the rest
+// height, with reflecting edges. Ticks that would break the CFL condition are split into
+// substeps.
+pub struct WaveEquationSolver {
+    pub speed: f32, // world units per second
+    pub damping: f32, // velocity kept per tick
+    pub restore: f32, // pull towards the rest height, per second squared
+    pub parallel: bool,
+}
+
+impl WaveEquationSolver {
+    pub fn new(speed: f32, damping: f32, restore: f32) -> Self {
+        WaveEquationSolver {
+            speed: speed,
+            damping: damping,
+            restore: restore,
+            parallel: false,
+        }
+    }
+
+    // how many samples a wave crosses in dt; must stay under MAX_COURANT per substep
+    pub fn courant_number(&self, spacing: f32, dt: f32) -> f32 {
+        self.speed * dt / spacing
+    }
+
+    pub fn substeps(&self, spacing: f32, dt: f32) -> u32 {
+        (self.courant_number(spacing, dt) / MAX_COURANT).ceil().max(1.0) as u32
+    }
+}
+
+impl WaveSolver for WaveEquationSolver {
+    fn reach(&self, spacing: f32, dt: f32) -> usize {
+        self.substeps(spacing, dt) as usize
+    }
+
+    fn step(&mut self, grid: &mut WaveGrid, dt: f32) {
+        let (width, depth) = grid.resolution;
+        let substeps = self.substeps(grid.spacing, dt);
+        let h = dt / substeps as f32;
+        let c2 = self.speed * self.speed / (grid.spacing * grid.spacing);
+        let (restore, damping) = (self.restore, self.damping);
+
+        for _ in 0..substeps {
+            {
+                let (height, origin) = (&*grid.height, grid.origin);
+                let d = |j: usize| height[j] - origin[j];
+
+                for_each_row(grid.velocity, width, self.parallel, |z, velocity| {
+                    for x in 0..width {
+                        let i = x + z * width;
+                        let here = d(i);
+
+                        // a missing neighbour mirrors this sample, i.e. no flow over the edge
+                        let laplacian = if x > 0 { d(i - 1) } else { here } +
+                                        if z > 0 { d(i - width) } else { here } +
+                                        if x + 1 < width { d(i + 1) } else { here } +
+                                        if z + 1 < depth { d(i + width) } else { here } -
+                                        4.0 * here;
+
+                        velocity[x] += (c2 * laplacian - restore * here) * h;
+                    }
+                });
+            }
+
+            let velocity = &*grid.velocity;
+            for_each_row(grid.height, width, self.parallel, |z, height| {
+                for x in 0..width {
+                    height[x] += velocity[x + z * width] * h;
+                }
+            });
+        }
+
+        for_each_row(grid.velocity, width, self.parallel, |_, velocity| {
+            for v in velocity.iter_mut() {
+                *v *= damping;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // wide and tall enough for several parallel bands, not square so that rows and columns can't
+    // get mixed up
+    const WIDTH: usize = 40;
+    const DEPTH: usize = 50;
+
+    fn run<S: WaveSolver>(solver: &mut S) -> (Vec<f32>, Vec<f32>) {
+        let origin = (0..WIDTH * DEPTH)
+            .map(|i| ((i % WIDTH) as f32 * 0.3).sin() * 4.0 + ((i / WIDTH) as f32 * 0.2).cos())
+            .collect::<Vec<f32>>();
+        let mut height = origin.clone();
+        let mut velocity = vec![0.0; WIDTH * DEPTH];
+        velocity[10 * WIDTH + 7] = 5.0;
+        velocity[37 * WIDTH + 30] = -3.0;
+
+        for _ in 0..50 {
+            let mut grid = WaveGrid {
+                height: &mut height,
+                origin: &origin,
+                velocity: &mut velocity,
+                resolution: (WIDTH, DEPTH),
+                spacing: 4.0,
+            };
+            solver.step(&mut grid, 0.01);
+        }
+        (height, velocity)
+    }
+
+    fn assert_same_bits(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+            assert!(x.to_bits() == y.to_bits(), "sample {} differs: {} != {}", i, x, y);
+        }
+    }
+
+    #[test]
+    fn spring_parallel_matches_serial() {
+        let serial = run(&mut SpringSolver::default());
+        let parallel = run(&mut SpringSolver { parallel: true, ..SpringSolver::default() });
+        assert_same_bits(&serial.0, &parallel.0);
+        assert_same_bits(&serial.1, &parallel.1);
+    }
+
+    #[test]
+    fn equation_parallel_matches_serial() {
+        let solver = || WaveEquationSolver::new(100.0, 0.998, 0.001);
+        let serial = run(&mut solver());
+        let parallel = run(&mut WaveEquationSolver { parallel: true, ..solver() });
+        assert_same_bits(&serial.0, &parallel.0);
+        assert_same_bits(&serial.1, &parallel.1);
+    }
+}
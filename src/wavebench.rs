@@ -0,0 +1,69 @@
+// Times the terrain wave solvers serially and on the rayon thread pool. The tests of the wave
+// module check that both ways give the same bits.
+//
+//     cargo run --release --bin wavebench
+
+extern crate rayon;
+
+mod wave;
+
+use std::time::Instant;
+use wave::{WaveGrid, WaveSolver, SpringSolver, WaveEquationSolver};
+
+const TICKS: usize = 100;
+const SPACING: f32 = 4.0;
+const DT: f32 = 0.01;
+
+// milliseconds per tick
+fn run<S: WaveSolver>(solver: &mut S, size: usize) -> f64 {
+    // some rolling hills and a few drops to get the waves going
+    let origin = (0..size * size)
+        .map(|i| ((i % size) as f32 * 0.1).sin() * 4.0 + ((i / size) as f32 * 0.07).cos() * 2.0)
+        .collect::<Vec<f32>>();
+    let mut height = origin.clone();
+    let mut velocity = vec![0.0; size * size];
+    for k in 1..9 {
+        velocity[(size / 9 * k) * size + size / 3] = 5.0;
+    }
+
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        let mut grid = WaveGrid {
+            height: &mut height,
+            origin: &origin,
+            velocity: &mut velocity,
+            resolution: (size, size),
+            spacing: SPACING,
+        };
+        solver.step(&mut grid, DT);
+    }
+    let elapsed = start.elapsed();
+
+    let ms = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1.0e6;
+    ms / TICKS as f64
+}
+
+fn compare<S: WaveSolver>(name: &str, size: usize, mut serial: S, mut parallel: S) {
+    let serial_ms = run(&mut serial, size);
+    let parallel_ms = run(&mut parallel, size);
+
+    println!("{:>8} {:>4}^2: serial {:8.3} ms/tick, parallel {:8.3} ms/tick, {:5.2}x",
+             name,
+             size,
+             serial_ms,
+             parallel_ms,
+             serial_ms / parallel_ms);
+}
+
+fn main() {
+    for &size in &[256, 512, 1024] {
+        compare("spring",
+                size,
+                SpringSolver::default(),
+                SpringSolver { parallel: true, ..SpringSolver::default() });
+        compare("equation",
+                size,
+                WaveEquationSolver::new(100.0, 0.998, 0.001),
+                WaveEquationSolver { parallel: true, ..WaveEquationSolver::new(100.0, 0.998, 0.001) });
+    }
+}
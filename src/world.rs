@@ -1,15 +1,24 @@
-use body::{Body, BodyShape, BodyConfig, BODY_CATEGORY_TERRAIN_BIT, BODY_COLLIDE_TERRAIN};
-use glium;
+use body::{Body, BodyHandle, BodyShape, BodyConfig, BodySnapshot, OdeGeomData, leaf_shapes,
+           BODY_CATEGORY_TERRAIN_BIT, BODY_COLLIDE_TERRAIN};
 use glium::backend::Facade;
+use heightmap::Heightmap;
 use mesh::Mesh;
 use ode;
-use na::Norm;
+use na;
+use na::{Norm, ToHomogeneous};
 use std;
 use math::*;
+use errors::*;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use mesh;
 use texture;
+use material::{Material, MaterialTable};
+use wave::{WaveGrid, WaveSolver, SpringSolver};
+use platform::{Platform, PlatformMotion, PathMode};
+use forcefield::ForceField;
+use liquid::{Liquid, LiquidSurface};
 
 unsafe extern "C" fn near_callback(user_data: *mut std::os::raw::c_void,
                                    ode_g1: ode::dGeomID,
@@ -18,41 +27,37 @@ unsafe extern "C" fn near_callback(user_data: *mut std::os::raw::c_void,
     let ode_b2 = ode::dGeomGetBody(ode_g2);
     let mut world: &mut World = &mut *(user_data as *mut World);
 
-    let (mut b1, mut b2) = if ode_b1 != std::ptr::null_mut() && ode_b2 != std::ptr::null_mut() {
-        // find references to Body instances
-        let mut bi1 = ode::dBodyGetData(ode_b1) as u64;
-        let mut bi2 = ode::dBodyGetData(ode_b2) as u64;
-
-        // bi1 and bi2 are the ids of some bodies in the bodies vec
-        // find indices of those bodies first and then obtain two mutable references to them
-        for (i, obj) in world.bodies.iter().enumerate() {
-            if obj.borrow().id == bi1 {
-                bi1 = i as u64;
-                break;
-            }
-        }
-        for (i, obj) in world.bodies.iter().enumerate() {
-            if obj.borrow().id == bi2 {
-                bi2 = i as u64;
-                break;
-            }
-        }
-        if bi1 == bi2 {
-            println!("{}", bi1);
+    let (r1, r2) = if ode_b1 != std::ptr::null_mut() && ode_b2 != std::ptr::null_mut() {
+        let h1 = BodyHandle::from_user_data(ode::dBodyGetData(ode_b1));
+        let h2 = BodyHandle::from_user_data(ode::dBodyGetData(ode_b2));
+        if h1 == h2 {
             return;
         }
-        assert!(bi1 != bi2);
-
-        if bi1 < bi2 {
-            let (begin, end) = world.bodies.split_at_mut(bi2 as usize);
-            (Some(begin[bi1 as usize].borrow_mut()), Some(end[0].borrow_mut()))
-        } else {
-            let (begin, end) = world.bodies.split_at_mut(bi1 as usize);
-            (Some(end[0].borrow_mut()), Some(begin[bi2 as usize].borrow_mut()))
-        }
+        (world.body(h1).cloned(), world.body(h2).cloned())
     } else {
         (None, None)
     };
+    let (b1, b2) = match (r1.as_ref(), r2.as_ref()) {
+        (Some(r1), Some(r2)) => (Some(r1.borrow()), Some(r2.borrow())),
+        _ => (None, None),
+    };
+
+    // sensors only need to know whether they overlap anything
+    if let (&Some(ref b1), &Some(ref b2)) = (&b1, &b2) {
+        if b1.config.sensor || b2.config.sensor {
+            let mut contact: ode::dContactGeom = std::mem::zeroed();
+            let numc = ode::dCollide(ode_g1,
+                                     ode_g2,
+                                     1,
+                                     &mut contact,
+                                     std::mem::size_of::<ode::dContactGeom>() as i32);
+            if numc > 0 {
+                world.record_sensor_overlap(b1, b2);
+                world.record_sensor_overlap(b2, b1);
+            }
+            return;
+        }
+    }
 
     const MAX_CONTACTS: usize = 100;
     let mut contact: [ode::dContact; MAX_CONTACTS] = std::mem::zeroed();
@@ -66,64 +71,433 @@ unsafe extern "C" fn near_callback(user_data: *mut std::os::raw::c_void,
     for i in 0..numc {
 
         let contact = &mut contact[i as usize];
-        // friction
-        contact.surface.mu = 50.0;
 
-        // rolling friction
-        // contact.surface.rho = 0.1;
+        if let (&Some(ref b1), &Some(ref b2)) = (&b1, &b2) {
+            let pos = Vec3::new(contact.geom.pos[0] as f32,
+                                contact.geom.pos[1] as f32,
+                                contact.geom.pos[2] as f32);
+            let m1 = world.contact_material(b1, pos);
+            let m2 = world.contact_material(b2, pos);
+            world.materials
+                .resolve(m1, &b1.config, m2, &b2.config)
+                .apply(&mut contact.surface);
+        } else {
+            let config = BodyConfig::default();
+            world.materials
+                .resolve(Material::Default, &config, Material::Default, &config)
+                .apply(&mut contact.surface);
+        }
+
+        let id = ode::dJointCreateContact(world.ode_world, world.ode_contact_group, contact);
+        ode::dJointAttach(id, ode_b1, ode_b2);
+    }
 
-        // rolling friction (spin direction, beyblade prevention)
-        // contact.surface.rhoN = 8000.0;
+    // one event per touching pair, from the deepest point
+    if let (&Some(ref b1), &Some(ref b2)) = (&b1, &b2) {
+        if numc > 0 {
+            let mut deepest = &contact[0];
+            for c in contact[1..numc as usize].iter() {
+                if c.geom.depth > deepest.geom.depth {
+                    deepest = c;
+                }
+            }
+            world.record_contact(b1, b2, &deepest.geom);
+        }
+    }
+}
 
-        // contact.surface.bounce = 0.0;
-        // contact.surface.mode |= ode::dContactBounce as i32;
+// position and rotation matrix in the layout that ODE uses
+fn iso_to_ode(iso: &Iso3) -> ([f64; 3], [f64; 12]) {
+    let m = iso.to_homogeneous();
+    let mut rotation = [0.0f64; 12];
+    for r in 0..3 {
+        for c in 0..3 {
+            rotation[r * 4 + c] = m[(r, c)] as f64;
+        }
+    }
+    ([m[(0, 3)] as f64, m[(1, 3)] as f64, m[(2, 3)] as f64], rotation)
+}
 
-        // contact.surface.mode |= ode::dContactRolling as i32;
+// Mass of a shape in its own frame, from the geoms that add_body made for its parts in order
+unsafe fn shape_mass(shape: &BodyShape,
+                     density: f32,
+                     geoms: &mut std::slice::Iter<(ode::dGeomID, Option<Iso3>)>)
+                     -> ode::dMass {
+    let density = density as f64;
+    let mut mass: ode::dMass = std::mem::zeroed();
+    ode::dMassSetZero(&mut mass);
+
+    if let BodyShape::Compound { ref parts } = *shape {
+        for &(_, ref part) in parts.iter() {
+            let part_mass = shape_mass(part, density as f32, geoms);
+            ode::dMassAdd(&mut mass, &part_mass);
+        }
+        return mass;
+    }
 
-        let mut ignore_collision = false;
-        // NOTE: handler if skipped if some geoms have no associated body
-        if let (&mut Some(ref mut b1), &mut Some(ref mut b2)) = (&mut b1, &mut b2) {
-            for mut handler in world.contact_handlers.iter_mut() {
-                if handler(&mut *b1, &mut *b2, contact) {
-                    ignore_collision = true;
+    let &(geom, offset) = geoms.next().expect("shape and geoms out of sync");
+    match *shape {
+        BodyShape::Sphere { radius } => ode::dMassSetSphere(&mut mass, density, radius as f64),
+        BodyShape::Box { size } => {
+            ode::dMassSetBox(&mut mass, density, size.x as f64, size.y as f64, size.z as f64)
+        }
+        // direction 3 is the z axis, like the geoms
+        BodyShape::Capsule { radius, length } => {
+            ode::dMassSetCapsule(&mut mass, density, 3, radius as f64, length as f64)
+        }
+        BodyShape::Cylinder { radius, length } => {
+            ode::dMassSetCylinder(&mut mass, density, 3, radius as f64, length as f64)
+        }
+        // the geom isn't attached to the body yet, so it's still at the origin
+        BodyShape::TriangleSoup { .. } => ode::dMassSetTrimesh(&mut mass, density, geom),
+        BodyShape::ConvexHull { ref points, ref polygons, .. } => {
+            // ODE can't weigh a convex, but it can weigh the same faces as a trimesh
+            let mut indices = Vec::new();
+            let mut i = 0;
+            while i < polygons.len() {
+                let n = polygons[i] as usize;
+                for k in 1..n - 1 {
+                    indices.extend(&[polygons[i + 1], polygons[i + 1 + k], polygons[i + 2 + k]]);
                 }
+                i += n + 1;
             }
+            let data = ode::dGeomTriMeshDataCreate();
+            ode::dGeomTriMeshDataBuildDouble(data,
+                                             points.as_ptr() as *const std::os::raw::c_void,
+                                             8 * 3,
+                                             points.len() as i32 / 3,
+                                             indices.as_ptr() as *const std::os::raw::c_void,
+                                             indices.len() as i32,
+                                             4 * 3);
+            let trimesh = ode::dCreateTriMesh(std::ptr::null_mut(), data, None, None, None);
+            ode::dMassSetTrimesh(&mut mass, density, trimesh);
+            ode::dGeomDestroy(trimesh);
+            ode::dGeomTriMeshDataDestroy(data);
         }
-        if !ignore_collision {
-            let id = ode::dJointCreateContact(world.ode_world, world.ode_contact_group, contact);
-            ode::dJointAttach(id, ode_b1, ode_b2);
+        BodyShape::Compound { .. } | BodyShape::HeightField => unreachable!(),
+    }
+
+    if let Some(offset) = offset {
+        let (position, rotation) = iso_to_ode(&offset);
+        ode::dMassRotate(&mut mass, rotation.as_ptr());
+        ode::dMassTranslate(&mut mass, position[0], position[1], position[2]);
+    }
+    mass
+}
+
+// Collects what a scene query geom touches; user data of query_callback
+struct QueryState {
+    geom: ode::dGeomID,
+    mask: u64,
+    contacts: Vec<(BodyHandle, ode::dContactGeom)>,
+}
+
+unsafe extern "C" fn query_callback(user_data: *mut std::os::raw::c_void,
+                                    ode_g1: ode::dGeomID,
+                                    ode_g2: ode::dGeomID) {
+    let query: &mut QueryState = &mut *(user_data as *mut QueryState);
+    let other = if ode_g1 == query.geom { ode_g2 } else { ode_g1 };
+    let ode_body = ode::dGeomGetBody(other);
+    if ode_body == std::ptr::null_mut() || ode::dGeomGetCategoryBits(other) & query.mask == 0 {
+        return;
+    }
+    let handle = BodyHandle::from_user_data(ode::dBodyGetData(ode_body));
+
+    const MAX_CONTACTS: usize = 16;
+    let mut contact: [ode::dContactGeom; MAX_CONTACTS] = std::mem::zeroed();
+    let numc = ode::dCollide(query.geom,
+                             other,
+                             MAX_CONTACTS as i32,
+                             &mut contact[0],
+                             std::mem::size_of::<ode::dContactGeom>() as i32);
+    for c in contact[..numc as usize].iter() {
+        query.contacts.push((handle, *c));
+    }
+}
+
+// A body found by a scene query. For rays and casts the distance is along the direction from
+// the origin, for overlaps from the center to the surface of the body. The normal points away
+// from the body.
+#[derive(Clone, Debug)]
+pub struct QueryHit {
+    pub body: BodyHandle,
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl QueryHit {
+    fn from_contact(body: BodyHandle, contact: &ode::dContactGeom, distance: f32) -> QueryHit {
+        QueryHit {
+            body: body,
+            point: Vec3::new(contact.pos[0] as f32, contact.pos[1] as f32, contact.pos[2] as f32),
+            normal: Vec3::new(contact.normal[0] as f32,
+                              contact.normal[1] as f32,
+                              contact.normal[2] as f32),
+            distance: distance,
         }
     }
 }
 
-type ContactHandlerT = Box<FnMut(&mut Body, &mut Body, &mut ode::dContact) -> bool + 'static>;
+// nearest first, only the nearest one of each body
+fn closest_hits(mut hits: Vec<QueryHit>) -> Vec<QueryHit> {
+    hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal));
+    let mut seen = HashSet::new();
+    hits.retain(|h| seen.insert(h.body));
+    hits
+}
+
+// Where the heightfield geom of a chunk reads its samples from
+struct ChunkSampler {
+    world: *const World,
+    min: (i32, i32),
+}
 
 unsafe extern "C" fn heightfield_callback(user_data: *mut std::os::raw::c_void,
                                           x: i32,
                                           z: i32)
                                           -> f64 {
-    let world: &mut World = &mut *(user_data as *mut World);
+    let sampler = &*(user_data as *const ChunkSampler);
+    let world = &*sampler.world;
+    let (x, z) = (x + sampler.min.0, z + sampler.min.1);
     world.heightfield[(x + z * world.heightfield_resolution.0) as usize] as f64
 }
 
+// how many ticks a chunk stays awake after it was woken up or the focus left it
+const CHUNK_WAKE_TICKS: u32 = 500;
+
+// most collision queries that one sphere cast makes
+const SPHERE_CAST_MAX_STEPS: u32 = 256;
+
+// A rectangle of the terrain with its own collision geom and render mesh. Neighbouring chunks
+// share the samples on their common edge; the samples themselves live in the world's
+// heightfield arrays.
+struct TerrainChunk {
+    // first sample and the number of samples along x and z
+    min: (i32, i32),
+    resolution: (i32, i32),
+    // end of the samples that the waves of this chunk update, exclusive; the shared edge
+    // belongs to the next chunk
+    owned_max: (i32, i32),
+    // world space middle and half size on the xz plane
+    center: Vec3,
+    half_size: (f32, f32),
+    body: Rc<RefCell<Body>>,
+    mesh: Option<Rc<RefCell<Mesh>>>,
+    mesh_idx: Vec<usize>,
+    // the geom reads through this, so it must not move and must outlive the body
+    _sampler: Box<ChunkSampler>,
+    // the waves of the chunk are stepped while this is nonzero
+    awake_ticks: u32,
+    // kept around so that stepping the waves doesn't allocate every tick
+    region: WaveRegion,
+    // the heights changed after the mesh was last updated
+    dirty: bool,
+}
+
+impl TerrainChunk {
+    // distance from position to the chunk on the xz plane, 0 when above or below it
+    fn distance(&self, position: Vec3) -> f32 {
+        let dx = ((position.x - self.center.x).abs() - self.half_size.0).max(0.0);
+        let dz = ((position.z - self.center.z).abs() - self.half_size.1).max(0.0);
+        (dx * dx + dz * dz).sqrt()
+    }
+
+    // whether the chunk's mesh uses any of the samples from min to max, exclusive
+    fn touches(&self, min: (i32, i32), max: (i32, i32)) -> bool {
+        // the normals of the mesh look one sample further
+        self.min.0 - 1 < max.0 && min.0 < self.min.0 + self.resolution.0 + 1 &&
+        self.min.1 - 1 < max.1 && min.1 < self.min.1 + self.resolution.1 + 1
+    }
+}
+
+// A chunk's samples and those around it that its waves depend on, stepped as a grid of its own
+#[derive(Default)]
+struct WaveRegion {
+    min: (i32, i32),
+    resolution: (i32, i32),
+    height: Vec<f32>,
+    origin: Vec<f32>,
+    velocity: Vec<f32>,
+}
+
+// Everything that World::step changes, for restarts and rewinding. Bodies added after the
+// snapshot are left alone on restore, and deleted ones are not brought back.
+#[derive(Clone)]
+pub struct Snapshot {
+    bodies: Vec<BodySnapshot>,
+    heightfield: Vec<f32>,
+    heightfield_origin: Vec<f32>,
+    heightfield_velocity: Vec<f32>,
+    wave_sources: Vec<WaveSource>,
+    platforms: Vec<Platform>,
+    touching: HashMap<(BodyHandle, BodyHandle), ContactEvent>,
+    sensor_overlaps: HashSet<(BodyHandle, BodyHandle)>,
+    chunk_awake_ticks: Vec<u32>,
+    leftover_dt: f32,
+    accum_dt: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContactPhase {
+    // the bodies started touching this tick
+    Begin,
+    // the bodies touched on the previous tick too
+    Persist,
+    // the bodies touched on the previous tick but not anymore; the rest is from that tick
+    End,
+}
+
+// Two bodies touching during a tick. The normal points towards the first body, and the normal
+// velocity is that of the first body relative to the second one at the contact point, negative
+// when they approach each other.
+#[derive(Clone, Debug)]
+pub struct ContactEvent {
+    pub bodies: (BodyHandle, BodyHandle),
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub normal_velocity: f32,
+    pub phase: ContactPhase,
+}
+
+impl ContactEvent {
+    pub fn involves(&self, handle: BodyHandle) -> bool {
+        self.bodies.0 == handle || self.bodies.1 == handle
+    }
+
+    // the other body of the pair if handle is one of them
+    pub fn other(&self, handle: BodyHandle) -> Option<BodyHandle> {
+        if self.bodies.0 == handle {
+            Some(self.bodies.1)
+        } else if self.bodies.1 == handle {
+            Some(self.bodies.0)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SensorPhase {
+    Enter,
+    Stay,
+    Exit,
+}
+
+// A body overlapping a sensor during a tick
+#[derive(Clone, Debug)]
+pub struct SensorEvent {
+    pub sensor: BodyHandle,
+    pub body: BodyHandle,
+    pub phase: SensorPhase,
+}
+
+// What happened during the ticks of one World::step, in tick order
+#[derive(Clone, Debug, Default)]
+pub struct StepEvents {
+    pub contacts: Vec<ContactEvent>,
+    pub sensors: Vec<SensorEvent>,
+}
+
+// Identifies a platform added with World::add_platform
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PlatformId(usize);
+
+// Identifies a wave source added with World::add_wave_source
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WaveSourceId(u32);
+
+// Identifies a force field added with World::add_force_field
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ForceFieldId(u32);
+
+// Identifies a liquid added with World::add_liquid
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LiquidId(u32);
+
+// How deform_terrain changes the ground; the effect fades out towards the edge of the brush
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum TerrainBrush {
+    // up by this many world units at the center
+    Raise(f32),
+    // down by this many world units at the center
+    Lower(f32),
+    // towards a height, all the way at the center with strength 1
+    Flatten { height: f32, strength: f32 },
+    // towards the average of the neighbouring samples, all the way at the center with strength 1
+    Smooth(f32),
+}
+
+// An oscillator that keeps pushing the terrain surface up and down around a point
+#[derive(Clone)]
+struct WaveSource {
+    id: WaveSourceId,
+    position: Vec3,
+    amplitude: f32,
+    frequency: f32,
+    radius: f32,
+    // seconds left to live, forever if None
+    lifetime: Option<f32>,
+    age: f32,
+}
+
+// A slot of the body slot map; the generation goes up whenever the body in it is deleted
+struct BodySlot {
+    generation: u32,
+    body: Option<Rc<RefCell<Body>>>,
+}
+
 pub struct World {
     ode_world: ode::dWorldID,
     ode_space: ode::dSpaceID,
     ode_contact_group: ode::dJointGroupID,
-    bodies: Vec<Rc<RefCell<Body>>>,
+    // shared with the bodies, so that the ones outliving the world know their ODE body is gone
+    alive: Rc<Cell<bool>>,
+    bodies: Vec<BodySlot>,
+    free_slots: Vec<u32>,
     leftover_dt: f32,
     accum_dt: f32,
-    contact_handlers: Vec<ContactHandlerT>,
-    body_id_counter: u64,
-
-    landscape_mesh: Option<Rc<RefCell<Mesh>>>,
+    // most ticks to take in one step; after a long hitch the rest of the time is skipped
+    max_substeps: u32,
+    // pairs touching on the previous tick, the ones found so far on this tick, and the events
+    // of the previous tick
+    touching: HashMap<(BodyHandle, BodyHandle), ContactEvent>,
+    new_touching: HashMap<(BodyHandle, BodyHandle), ContactEvent>,
+    tick_contacts: Vec<ContactEvent>,
+    // the same for sensors, as (sensor, other body)
+    sensor_overlaps: HashSet<(BodyHandle, BodyHandle)>,
+    new_sensor_overlaps: HashSet<(BodyHandle, BodyHandle)>,
+    tick_sensors: Vec<SensorEvent>,
+
+    // row by row along x
+    chunks: Vec<TerrainChunk>,
+    chunk_columns: i32,
+    // the chunk bodies, for telling terrain contacts apart quickly
+    terrain_bodies: HashSet<BodyHandle>,
+    // chunks closer than the wake distance to the focus, usually the player, keep their waves
+    // going; no focus keeps everything awake
+    terrain_focus: Option<Vec3>,
+    pub terrain_wake_distance: f32,
+
+    materials: MaterialTable,
+    // per heightfield sample, empty if the whole terrain is made of its body material
+    terrain_materials: Vec<Material>,
 
     pub heightfield: Vec<f32>,
     pub heightfield_origin: Vec<f32>,
     pub heightfield_velocity: Vec<f32>,
     pub heightfield_resolution: (i32, i32),
-    pub heightfield_idx: Vec<usize>,
     pub heightfield_scale: f32,
+
+    wave_solver: Box<WaveSolver>,
+    wave_sources: Vec<WaveSource>,
+    next_wave_source: u32,
+    platforms: Vec<Platform>,
+    force_fields: Vec<(ForceFieldId, ForceField)>,
+    next_force_field: u32,
+    liquids: Vec<(LiquidId, Liquid)>,
+    next_liquid: u32,
 }
 
 impl World {
@@ -146,18 +520,38 @@ impl World {
             ode_world: ode_world,
             ode_space: ode_space,
             ode_contact_group: unsafe { ode::dJointGroupCreate(0) },
+            alive: Rc::new(Cell::new(true)),
             leftover_dt: 0.0,
             accum_dt: 0.0,
+            max_substeps: 10,
             bodies: Vec::new(),
-            contact_handlers: Vec::new(),
-            body_id_counter: 0,
+            free_slots: Vec::new(),
+            sensor_overlaps: HashSet::new(),
+            new_sensor_overlaps: HashSet::new(),
+            tick_sensors: Vec::new(),
+            touching: HashMap::new(),
+            new_touching: HashMap::new(),
+            tick_contacts: Vec::new(),
             heightfield: Vec::new(),
             heightfield_origin: Vec::new(),
             heightfield_velocity: Vec::new(),
             heightfield_resolution: (0, 0),
             heightfield_scale: scale,
-            heightfield_idx: Vec::new(),
-            landscape_mesh: None,
+            chunks: Vec::new(),
+            chunk_columns: 0,
+            terrain_bodies: HashSet::new(),
+            terrain_focus: None,
+            terrain_wake_distance: std::f32::INFINITY,
+            materials: Default::default(),
+            terrain_materials: Vec::new(),
+            wave_solver: Box::new(SpringSolver::default()),
+            wave_sources: Vec::new(),
+            next_wave_source: 0,
+            platforms: Vec::new(),
+            force_fields: Vec::new(),
+            next_force_field: 0,
+            liquids: Vec::new(),
+            next_liquid: 0,
         }
     }
 
@@ -169,337 +563,1891 @@ impl World {
         self.ode_space
     }
 
-    pub fn add_contact_handler(&mut self, handler: ContactHandlerT) {
-        self.contact_handlers.push(handler);
+    pub fn set_wave_solver(&mut self, solver: Box<WaveSolver>) {
+        self.wave_solver = solver;
     }
 
-    pub fn add_body(&mut self,
-                    mesh: Rc<RefCell<mesh::Mesh>>,
-                    texture: Rc<texture::Texture>,
-                    shape: Rc<BodyShape>,
-                    config: BodyConfig)
-                    -> Rc<RefCell<Body>> {
+    // Start oscillating the terrain around position; amplitude is in the same units as the
+    // velocity kick of impulse(), radius in world units. A source without a lifetime runs until
+    // it's removed.
+    pub fn add_wave_source(&mut self,
+                           position: Vec3,
+                           amplitude: f32,
+                           frequency: f32,
+                           radius: f32,
+                           lifetime: Option<f32>)
+                           -> WaveSourceId {
+        let id = WaveSourceId(self.next_wave_source);
+        self.next_wave_source += 1;
+        self.wave_sources.push(WaveSource {
+            id: id,
+            position: position,
+            amplitude: amplitude,
+            frequency: frequency,
+            radius: radius,
+            lifetime: lifetime,
+            age: 0.0,
+        });
+        id
+    }
 
-        let ode_body = unsafe { ode::dBodyCreate(self.ode_world) };
+    #[allow(dead_code)]
+    pub fn remove_wave_source(&mut self, id: WaveSourceId) {
+        self.wave_sources.retain(|s| s.id != id);
+    }
 
-        let ode_geom = match *shape {
-            BodyShape::Sphere { radius } => unsafe {
-                ode::dCreateSphere(self.ode_space, radius as f64)
-            },
-            BodyShape::TriangleSoup { ref vertices, ref indices } => {
-                unsafe {
-                    let trimesh_data = ode::dGeomTriMeshDataCreate();
+    // Start pushing the bodies inside the field on every tick until it's removed
+    pub fn add_force_field(&mut self, field: ForceField) -> ForceFieldId {
+        let id = ForceFieldId(self.next_force_field);
+        self.next_force_field += 1;
+        self.force_fields.push((id, field));
+        id
+    }
 
-                    ode::dGeomTriMeshDataBuildDouble(trimesh_data,
-                                                     vertices.as_ptr() as
-                                                     *const std::os::raw::c_void,
-                                                     8 * 3, // vertex stride
-                                                     vertices.len() as i32 / 3,
-                                                     indices.as_ptr() as
-                                                     *const std::os::raw::c_void,
-                                                     indices.len() as i32,
-                                                     4 * 3);
+    #[allow(dead_code)]
+    pub fn remove_force_field(&mut self, id: ForceFieldId) {
+        self.force_fields.retain(|&(field_id, _)| field_id != id);
+    }
 
-                    ode::dCreateTriMesh(self.ode_space, trimesh_data, None, None, None)
+    #[allow(dead_code)]
+    pub fn force_field_mut(&mut self, id: ForceFieldId) -> Option<&mut ForceField> {
+        self.force_fields.iter_mut().find(|&&mut (field_id, _)| field_id == id).map(|f| &mut f.1)
+    }
 
-                }
-            }
-            _ => unreachable!(), // heightfield is special
-        };
+    // Start floating and slowing down the bodies in the liquid until it's removed
+    pub fn add_liquid(&mut self, liquid: Liquid) -> LiquidId {
+        let id = LiquidId(self.next_liquid);
+        self.next_liquid += 1;
+        self.liquids.push((id, liquid));
+        id
+    }
 
-        println!("Create body {:?}", config);
-        unsafe {
-            ode::dBodySetData(ode_body, self.body_id_counter as *mut std::os::raw::c_void);
-            ode::dBodySetPosition(ode_body, 0.0, 0.0, 0.0);
-            if config.fixed {
-                ode::dBodySetKinematic(ode_body);
-            } else {
-                ode::dBodySetDynamic(ode_body);
-                let mut mass: ode::dMass = std::mem::zeroed();
-                ode::dMassSetSphere(&mut mass, config.density as f64, 1.0);
-                ode::dBodySetMass(ode_body, &mass);
-            }
-            ode::dGeomSetBody(ode_geom, ode_body);
+    #[allow(dead_code)]
+    pub fn remove_liquid(&mut self, id: LiquidId) {
+        self.liquids.retain(|&(liquid_id, _)| liquid_id != id);
+    }
 
-            ode::dGeomSetCategoryBits(ode_geom, config.category_bits);
-            ode::dGeomSetCollideBits(ode_geom, config.collide_bits);
-        };
+    #[allow(dead_code)]
+    pub fn liquid_mut(&mut self, id: LiquidId) -> Option<&mut Liquid> {
+        self.liquids.iter_mut().find(|&&mut (liquid_id, _)| liquid_id == id).map(|l| &mut l.1)
+    }
 
-        let body = Rc::new(RefCell::new(Body {
-            mesh: Some(mesh),
-            shape: shape,
-            texture: Some(texture),
-            config: config.clone(),
-            ode_body: ode_body,
-            ode_geom: ode_geom,
-            id: self.body_id_counter,
-            collide_sound: config.collide_sound,
-            shaded: false,
-        }));
-        self.bodies.push(body.clone());
-        self.body_id_counter += 1;
-        body
+    // Height of the top of a liquid at a world point
+    pub fn liquid_surface_at(&self, liquid: &Liquid, x: f32, z: f32) -> f32 {
+        match liquid.surface {
+            LiquidSurface::Level(height) => height,
+            LiquidSurface::Terrain { depth } => self.height_at(x, z) + depth,
+        }
     }
 
-    pub fn setup_heightfield<F: Facade>(&mut self,
-                                        f: &F,
-                                        texture: &glium::texture::RawImage2d<'static, u8>,
-                                        visible_texture: Rc<texture::Texture>)
-                                        -> Rc<RefCell<Body>> {
+    // Kick the terrain once around position, in a 7x7 sample neighbourhood falling off with
+    // the squared distance
+    pub fn impulse(&mut self, position: Vec3, strength: f32) {
+        self.add_wave_velocity(position, 3, |d2| strength / (1.0 + d2));
+    }
 
-        // create mesh based on texture
+    // Add falloff(squared distance in samples) to the surface velocity of the samples within
+    // radius samples from the one under position
+    fn add_wave_velocity<F>(&mut self, position: Vec3, radius: i32, falloff: F)
+        where F: Fn(f32) -> f32
+    {
+        if !(position.x.is_finite() && position.z.is_finite()) {
+            return;
+        }
 
-        let (mesh, reso, hfield, idx) = Mesh::from_texture(f, texture, self.heightfield_scale);
-        let mesh = mesh.expect("mesh load fail");
+        let (width, depth) = self.heightfield_resolution;
+        let scale = self.heightfield_scale;
+        let (gx, gz) = self.world_to_grid(position.x, position.z);
+        let (cx, cz) = (gx.round() as i32, gz.round() as i32);
+
+        for zz in -radius..radius + 1 {
+            for xx in -radius..radius + 1 {
+                let (x, z) = (cx + xx, cz + zz);
+                if x >= 0 && x < width && z >= 0 && z < depth {
+                    self.heightfield_velocity[(x + z * width) as usize] +=
+                        falloff((xx * xx + zz * zz) as f32);
+                }
+            }
+        }
+        self.wake_terrain(position, radius as f32 * scale);
+    }
 
-        self.heightfield_idx = idx;
+    // Chunks near this stay awake; the others stop their waves a while after the focus leaves
+    pub fn set_terrain_focus(&mut self, position: Vec3) {
+        self.terrain_focus = Some(position);
+    }
 
+    // Keep the waves of the chunks within radius from position going for a while
+    pub fn wake_terrain(&mut self, position: Vec3, radius: f32) {
+        for chunk in self.chunks.iter_mut() {
+            if chunk.distance(position) <= radius {
+                chunk.awake_ticks = chunk.awake_ticks.max(CHUNK_WAKE_TICKS);
+            }
+        }
+    }
 
-        self.heightfield = hfield.clone();
-        self.heightfield_velocity = vec![0.0; hfield.len()];
-        self.heightfield_origin = hfield;
-        self.heightfield_resolution = reso;
+    // World x and z of a point in heightfield sample coordinates; the terrain is centered at
+    // the origin
+    pub fn grid_to_world(&self, x: f32, z: f32) -> (f32, f32) {
+        let (width, depth) = self.heightfield_resolution;
+        let s = self.heightfield_scale;
+        (x * s - s * 0.5 * (width - 1) as f32, z * s - s * 0.5 * (depth - 1) as f32)
+    }
 
-        let heightfield_data = unsafe { ode::dGeomHeightfieldDataCreate() };
+    // Heightfield sample coordinates of a world point, fractional between the samples
+    pub fn world_to_grid(&self, x: f32, z: f32) -> (f32, f32) {
+        let (width, depth) = self.heightfield_resolution;
+        let s = self.heightfield_scale;
+        (x / s + (width - 1) as f32 * 0.5, z / s + (depth - 1) as f32 * 0.5)
+    }
 
-        let scale = 1.0; // "vertical height scale multiplier"
-        let offset = 0.0f64; // vetical height offset
-        let thickness = 0.1;
-        let wrap = false as i32; // whether to wrap the heightfield infinitely
+    // The samples around a world point and the position between them, clamped to the terrain
+    fn height_cell(&self, x: f32, z: f32) -> ([f32; 4], f32, f32) {
+        let (width, depth) = self.heightfield_resolution;
+        let (gx, gz) = self.world_to_grid(x, z);
+        let gx = na::clamp(gx, 0.0, (width - 1) as f32);
+        let gz = na::clamp(gz, 0.0, (depth - 1) as f32);
+        let x0 = (gx.floor() as i32).min(width - 2);
+        let z0 = (gz.floor() as i32).min(depth - 2);
+
+        let h = |x: i32, z: i32| self.heightfield[(x + z * width) as usize];
+        ([h(x0, z0), h(x0 + 1, z0), h(x0, z0 + 1), h(x0 + 1, z0 + 1)],
+         gx - x0 as f32,
+         gz - z0 as f32)
+    }
 
-        unsafe {
-            ode::dGeomHeightfieldDataBuildCallback(heightfield_data,
-                                                   // user ptr is self
-                                                   self as *mut _ as *mut std::os::raw::c_void,
-                                                   Some(heightfield_callback),
-                                                   (self.heightfield_resolution.0 as f64 - 1.0) *
-                                                   self.heightfield_scale as f64,
-                                                   (self.heightfield_resolution.1 as f64 - 1.0) *
-                                                   self.heightfield_scale as f64,
-                                                   self.heightfield_resolution.0,
-                                                   self.heightfield_resolution.1,
-                                                   scale,
-                                                   offset,
-                                                   thickness,
-                                                   wrap);
-            ode::dGeomHeightfieldDataSetBounds(heightfield_data,
-                                               -5000.0, // min height
-                                               5000.0 /* max height */);
-        };
+    // Terrain height at a world point, interpolated bilinearly between the samples. Outside the
+    // terrain this is the height at its nearest edge.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let ([h00, h10, h01, h11], fx, fz) = self.height_cell(x, z);
+        let h0 = h00 + (h10 - h00) * fx;
+        let h1 = h01 + (h11 - h01) * fx;
+        h0 + (h1 - h0) * fz
+    }
 
-        let geom =
-            unsafe { ode::dCreateHeightfield(self.ode_space, heightfield_data, true as i32) };
+    // Unit normal of the interpolated terrain surface at a world point
+    #[allow(dead_code)]
+    pub fn normal_at(&self, x: f32, z: f32) -> Vec3 {
+        let ([h00, h10, h01, h11], fx, fz) = self.height_cell(x, z);
+        let s = self.heightfield_scale;
+        let dx = ((h10 - h00) * (1.0 - fz) + (h11 - h01) * fz) / s;
+        let dz = ((h01 - h00) * (1.0 - fx) + (h11 - h10) * fx) / s;
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
 
-        unsafe {
-            // FIXME: use add_body
-            let ode_body = ode::dBodyCreate(self.ode_world);
-            ode::dGeomSetBody(geom, ode_body);
-            ode::dBodySetData(ode_body, self.body_id_counter as *mut std::os::raw::c_void);
-            ode::dBodySetKinematic(ode_body);
-            // ode::dGeomSetBody(geom, std::ptr::null_mut());
-            ode::dGeomSetPosition(geom, 0.0, 0.0, 0.0);
-            ode::dBodySetPosition(ode_body, 0.0, 0.0, 0.0);
-            ode::dGeomSetCategoryBits(geom, BODY_CATEGORY_TERRAIN_BIT);
-            ode::dGeomSetCollideBits(geom, BODY_COLLIDE_TERRAIN);
-            let mesh = Rc::new(RefCell::new(mesh));
-            self.landscape_mesh = Some(mesh.clone());
-            let body = Rc::new(RefCell::new(Body {
-                mesh: Some(mesh),
-                shape: Rc::new(BodyShape::HeightField),
-                texture: Some(visible_texture),
-                config: Default::default(),
-                ode_body: ode_body,
-                ode_geom: geom,
-                id: self.body_id_counter,
-                collide_sound: None,
-                shaded: true,
-            }));
-            self.body_id_counter += 1;
-            self.bodies.push(body.clone());
-            body
-        }
+    // whether the body is a part of the terrain
+    pub fn is_terrain(&self, handle: BodyHandle) -> bool {
+        self.terrain_bodies.contains(&handle)
     }
 
-    pub fn del_body(&mut self, body_id: u64 /* body: &Body */) {
-        // assume it's found because it's added earlier
-        // let idx = self.bodies.iter().position(|ref x| *x.borrow() == *body).unwrap();
-        let idx = self.bodies.iter().position(|ref x| x.borrow().id == body_id).unwrap();
-        unsafe {
-            ode::dSpaceRemove(self.ode_space, self.bodies[idx].borrow().ode_geom);
-            // Should do these when the body gets dropped
-            // ode::dGeomDestroy(body.ode_geom);
-            // ode::dBodyDestroy(body.ode_body);
-            //
+    // Count down the sleep timers, waking up the chunks near the focus
+    fn update_chunk_sleep(&mut self) {
+        let (focus, distance) = (self.terrain_focus, self.terrain_wake_distance);
+        for chunk in self.chunks.iter_mut() {
+            if focus.map_or(true, |f| chunk.distance(f) <= distance) {
+                chunk.awake_ticks = CHUNK_WAKE_TICKS;
+            } else {
+                chunk.awake_ticks = chunk.awake_ticks.saturating_sub(1);
+            }
         }
-        self.bodies.remove(idx);
     }
 
-    // Advance the world state forwards by dt seconds
-    pub fn step(&mut self,
-                frame_dt: f32,
-                player_position: Vec3,
-                player_action: bool,
-                _p: (f32, f32, f32)) {
-        self.leftover_dt += frame_dt;
-
-        while self.leftover_dt >= PHYS_DT {
-            self.leftover_dt -= PHYS_DT;
-            self.accum_dt += PHYS_DT;
+    // Step the waves of the awake chunks. Each is stepped on a copy of its samples and enough
+    // of its neighbours' around it, so while everything's awake the result is the same as
+    // stepping the whole terrain at once; the edge of a sleeping neighbour just holds still.
+    fn step_waves(&mut self, dt: f32) {
+        let (width, depth) = self.heightfield_resolution;
+        let reach = self.wave_solver.reach(self.heightfield_scale, dt) as i32;
+        let &mut World { ref mut chunks,
+                         ref mut heightfield,
+                         ref heightfield_origin,
+                         ref mut heightfield_velocity,
+                         ref mut wave_solver,
+                         heightfield_scale,
+                         chunk_columns,
+                         .. } = self;
 
-            let player_velocity = self.bodies[0].borrow_mut().get_linear_velocity();
-            let effect_position = player_position - player_velocity.normalize() * 8.0;
-            let amplitude = Vec3::new(player_velocity.x, 0.0, player_velocity.z).norm() / 200.0;
-
-            if player_action || amplitude > 0.03 {
-                let amplitude = amplitude - 0.03;
-                // self.heightfield_velocity[p] += 1.0;
-
-                let offset =
-                    effect_position +
-                    Vec3::new(self.heightfield_resolution.0 as f32 *
-                              self.heightfield_scale as f32 * 0.5,
-                              0.0,
-                              self.heightfield_resolution.1 as f32 *
-                              self.heightfield_scale as f32 * 0.5);
-
-
-                for xx in -3..4 {
-                    for zz in -3..4 {
-                        let xcoord = (offset.x / self.heightfield_scale).floor() as i32 + xx;
-                        let zcoord = (offset.z / self.heightfield_scale).floor() as i32 + zz;
-                        let p = xcoord + zcoord * self.heightfield_resolution.0 as i32;
-                        if p >= 0 && (p as usize) < self.heightfield.len() {
-                            self.heightfield_velocity[p as usize] +=
-                                amplitude * 1.0 / (1.0 + (xx * xx + zz * zz) as f32);
-                        }
-                    }
-                }
+        // copy everything out first so that no chunk sees its neighbours from after this tick
+        for chunk in chunks.iter_mut().filter(|c| c.awake_ticks > 0) {
+            let min = ((chunk.min.0 - reach).max(0), (chunk.min.1 - reach).max(0));
+            let max = ((chunk.owned_max.0 + reach).min(width),
+                       (chunk.owned_max.1 + reach).min(depth));
+            let region = &mut chunk.region;
+            region.min = min;
+            region.resolution = (max.0 - min.0, max.1 - min.1);
+            region.height.clear();
+            region.origin.clear();
+            region.velocity.clear();
+            for z in min.1..max.1 {
+                let row = (min.0 + z * width) as usize..(max.0 + z * width) as usize;
+                region.height.extend_from_slice(&heightfield[row.clone()]);
+                region.origin.extend_from_slice(&heightfield_origin[row.clone()]);
+                region.velocity.extend_from_slice(&heightfield_velocity[row]);
             }
+        }
 
-            const WAVE_DT: f32 = 0.1;
+        for chunk in chunks.iter_mut().filter(|c| c.awake_ticks > 0) {
+            let region = &mut chunk.region;
+            let mut grid = WaveGrid {
+                height: &mut region.height,
+                origin: &region.origin,
+                velocity: &mut region.velocity,
+                resolution: (region.resolution.0 as usize, region.resolution.1 as usize),
+                spacing: heightfield_scale,
+            };
+            wave_solver.step(&mut grid, dt);
+        }
 
-            for v in self.heightfield_velocity.iter_mut() {
-                *v *= 0.998;
+        for chunk in chunks.iter().filter(|c| c.awake_ticks > 0) {
+            let region = &chunk.region;
+            for z in chunk.min.1..chunk.owned_max.1 {
+                for x in chunk.min.0..chunk.owned_max.0 {
+                    let (rx, rz) = (x - region.min.0, z - region.min.1);
+                    let i = (x + z * width) as usize;
+                    let j = (rx + rz * region.resolution.0) as usize;
+                    heightfield[i] = region.height[j];
+                    heightfield_velocity[i] = region.velocity[j];
+                }
             }
+        }
 
-            for x in 0..self.heightfield_resolution.0 {
-                for z in 0..self.heightfield_resolution.1 {
+        // the neighbours' meshes share the edges and look further in for the normals; two
+        // chunks either way covers that even for chunks of a single sample
+        let rows = chunks.len() as i32 / chunk_columns.max(1);
+        for i in 0..chunks.len() {
+            if chunks[i].awake_ticks == 0 {
+                continue;
+            }
+            let (min, max) = (chunks[i].min, chunks[i].owned_max);
+            let (cx, cz) = (i as i32 % chunk_columns, i as i32 / chunk_columns);
+            for nz in (cz - 2).max(0)..(cz + 3).min(rows) {
+                for nx in (cx - 2).max(0)..(cx + 3).min(chunk_columns) {
+                    let neighbour = &mut chunks[(nx + nz * chunk_columns) as usize];
+                    if neighbour.touches(min, max) {
+                        neighbour.dirty = true;
+                    }
+                }
+            }
+        }
+    }
 
-                    let stride = self.heightfield_resolution.0 as usize;
-                    let i = (x as usize + z as usize * stride) as usize;
+    // Reshape the ground for good within radius world units from position. The rest height
+    // changes and the surface moves along with it, so any waves on it carry on.
+    #[allow(dead_code)]
+    pub fn deform_terrain(&mut self, position: Vec3, radius: f32, brush: TerrainBrush) {
+        if self.heightfield.is_empty() || !(radius > 0.0) {
+            return;
+        }
+        let (width, depth) = self.heightfield_resolution;
+        let (gx, gz) = self.world_to_grid(position.x, position.z);
+        let r = radius / self.heightfield_scale;
+        let min = (((gx - r).floor() as i32).max(0), ((gz - r).floor() as i32).max(0));
+        let max = (((gx + r).ceil() as i32 + 1).min(width),
+                   ((gz + r).ceil() as i32 + 1).min(depth));
+        if min.0 >= max.0 || min.1 >= max.1 {
+            return;
+        }
 
-                    let neighs = 0.0 +
-                                 if x > 0 {
-                        self.heightfield[i - 1] - self.heightfield_origin[i - 1]
-                    } else {
-                        0.0
-                    } +
-                                 if z > 0 {
-                        self.heightfield[i - stride] - self.heightfield_origin[i - stride]
-                    } else {
-                        0.0
-                    } +
-                                 if x + 1 < self.heightfield_resolution.0 {
-                        self.heightfield[i + 1] - self.heightfield_origin[i + 1]
-                    } else {
-                        0.0
-                    } +
-                                 if z + 1 < self.heightfield_resolution.1 {
-                        self.heightfield[i + stride] - self.heightfield_origin[i + stride]
-                    } else {
-                        0.0
-                    };
+        // smoothing looks at the neighbours as they were before this, so it needs a copy of the
+        // brush area and one more sample around it
+        let copy_min = ((min.0 - 1).max(0), (min.1 - 1).max(0));
+        let copy_max = ((max.0 + 1).min(width), (max.1 + 1).min(depth));
+        let copy_width = copy_max.0 - copy_min.0;
+        let mut before = Vec::new();
+        if let TerrainBrush::Smooth(_) = brush {
+            for z in copy_min.1..copy_max.1 {
+                let row = (copy_min.0 + z * width) as usize..(copy_max.0 + z * width) as usize;
+                before.extend_from_slice(&self.heightfield_origin[row]);
+            }
+        }
+        let at = |x: i32, z: i32| {
+            let (x, z) = (na::clamp(x, 0, width - 1), na::clamp(z, 0, depth - 1));
+            before[((x - copy_min.0) + (z - copy_min.1) * copy_width) as usize]
+        };
 
-                    self.heightfield_velocity[i] +=
-                        (neighs / 4.0 - (self.heightfield[i] - self.heightfield_origin[i])) *
-                        WAVE_DT;
+        for z in min.1..max.1 {
+            for x in min.0..max.0 {
+                let (dx, dz) = (x as f32 - gx, z as f32 - gz);
+                let d = (dx * dx + dz * dz).sqrt() / r;
+                if d >= 1.0 {
+                    continue;
                 }
+                let t = 1.0 - d;
+                let weight = t * t * (3.0 - 2.0 * t);
+
+                let i = (x + z * width) as usize;
+                let old = self.heightfield_origin[i];
+                let new = match brush {
+                    TerrainBrush::Raise(amount) => old + amount * weight,
+                    TerrainBrush::Lower(amount) => old - amount * weight,
+                    TerrainBrush::Flatten { height, strength } => {
+                        old + (height - old) * strength * weight
+                    }
+                    TerrainBrush::Smooth(strength) => {
+                        let avg = (at(x - 1, z) + at(x + 1, z) + at(x, z - 1) + at(x, z + 1)) / 4.0;
+                        old + (avg - old) * strength * weight
+                    }
+                };
+                self.heightfield_origin[i] = new;
+                self.heightfield[i] += new - old;
             }
+        }
+
+        // the geoms read the samples directly, only the meshes need to know
+        self.mark_chunks_dirty(min, max);
+        self.update_landscape_mesh();
+    }
 
-            for ((x, o), v) in self.heightfield
-                .iter_mut()
-                .zip(self.heightfield_origin.iter())
-                .zip(self.heightfield_velocity.iter_mut()) {
-                *v += (*o - *x) * WAVE_DT * 0.001;
+    // Update the meshes that show any of the samples from min to max, exclusive
+    fn mark_chunks_dirty(&mut self, min: (i32, i32), max: (i32, i32)) {
+        for chunk in self.chunks.iter_mut() {
+            if chunk.touches(min, max) {
+                chunk.dirty = true;
             }
-            for (x, v) in self.heightfield.iter_mut().zip(self.heightfield_velocity.iter()) {
-                *x += *v * WAVE_DT;
+        }
+    }
+
+    // Run the wave sources for one tick and forget the expired ones
+    fn step_wave_sources(&mut self, dt: f32) {
+        let mut sources = std::mem::replace(&mut self.wave_sources, Vec::new());
+        for s in &mut sources {
+            s.age += dt;
+            let kick = s.amplitude * (2.0 * std::f32::consts::PI * s.frequency * s.age).sin();
+            let radius = s.radius / self.heightfield_scale;
+            self.add_wave_velocity(s.position, radius.ceil() as i32, |d2| {
+                let t = 1.0 - d2.sqrt() / radius;
+                if t > 0.0 { kick * t } else { 0.0 }
+            });
+            if let Some(ref mut left) = s.lifetime {
+                *left -= dt;
             }
-            unsafe {
-                ode::dSpaceCollide(self.ode_space,
-                                   self as *mut _ as *mut std::os::raw::c_void,
-                                   Some(near_callback));
+        }
+        sources.retain(|s| s.lifetime.map_or(true, |left| left > 0.0));
+        self.wave_sources = sources;
+    }
 
-                ode::dWorldStep(self.ode_world, PHYS_DT as f64);
-                ode::dJointGroupEmpty(self.ode_contact_group);
+    // Move a fixed body along a path; it starts from the beginning of the path and from the
+    // orientation it has now
+    pub fn add_platform(&mut self,
+                        body: BodyHandle,
+                        motion: PlatformMotion,
+                        mode: PathMode)
+                        -> PlatformId {
+        let mut platform = Platform::new(body, motion, mode);
+        if let Some(b) = self.body(body) {
+            unsafe {
+                let q = ode::dBodyGetQuaternion(b.borrow().ode_body);
+                platform.base_quaternion = [*q.offset(0), *q.offset(1), *q.offset(2), *q.offset(3)];
             }
         }
+        self.platforms.push(platform);
+        PlatformId(self.platforms.len() - 1)
+    }
 
-        // deform mesh based on heightfield
+    pub fn platform_mut(&mut self, id: PlatformId) -> &mut Platform {
+        &mut self.platforms[id.0]
+    }
 
-        let &mut World { ref mut landscape_mesh,
-                         ref heightfield,
-                         // ref heightfield_origin,
-                         ref heightfield_idx,
-                         ref heightfield_velocity,
-                         ref heightfield_resolution,
-                         .. } = self;
-        let mut mesh = landscape_mesh.as_mut().unwrap().borrow_mut();
-
-        mesh.update_mesh(|orig_verts, new_verts| {
-            for (index, (_orig_vert, gpu_vert)) in orig_verts.iter()
-                .zip(new_verts.iter_mut())
-                .enumerate() {
-
-                use na::Norm;
-                let hi = heightfield_idx[index];
-                // let offset = heightfield[hi] - heightfield_origin[hi];
-                // let velo = heightfield_velocity[hi];
-
-                // if offset.abs() < 0.01 {
-                //    *gpu_vert = *orig_vert;
-                //    continue;
-                // }
-
-                let xi = heightfield_idx[index] % heightfield_resolution.0 as usize;
-                let zi = heightfield_idx[index] / heightfield_resolution.0 as usize;
-                let h = heightfield[hi];
-
-                let xm = if xi > 0 { heightfield[hi - 1] } else { 0.0 };
-                let xp = if xi + 1 < heightfield_resolution.0 as usize {
-                    heightfield[hi + 1]
-                } else {
-                    0.0
-                };
-                let zm = if zi > 0 {
-                    heightfield[hi - heightfield_resolution.0 as usize]
-                } else {
-                    0.0
-                };
-                let zp = if zi + 1 < heightfield_resolution.1 as usize {
-                    heightfield[hi + heightfield_resolution.0 as usize]
-                } else {
-                    0.0
-                };
+    // Put each platform body where its path is now and give it the velocity that takes it to
+    // where the path is after dt, so that whatever rests on it moves along
+    fn update_platforms(&mut self, dt: f32) {
+        for i in 0..self.platforms.len() {
+            let body = match self.body(self.platforms[i].body) {
+                Some(body) => body.clone(),
+                None => continue,
+            };
+            let mut body = body.borrow_mut();
+            let platform = &mut self.platforms[i];
+
+            let position = platform.position();
+            let rotation = platform.rotation();
+            // snapping to the path keeps the integration from drifting
+            if let Some(position) = position {
+                body.set_position(position);
+            }
+            if let Some((axis, angle)) = rotation {
+                let mut q_angle = [0.0; 4];
+                let mut q = [0.0; 4];
+                unsafe {
+                    ode::dQFromAxisAndAngle(q_angle.as_mut_ptr(),
+                                            axis.x as f64,
+                                            axis.y as f64,
+                                            axis.z as f64,
+                                            angle as f64);
+                    ode::dQMultiply0(q.as_mut_ptr(),
+                                     q_angle.as_ptr(),
+                                     platform.base_quaternion.as_ptr());
+                    ode::dBodySetQuaternion(body.ode_body, q.as_ptr());
+                }
+            }
+
+            platform.advance(dt);
+
+            if let (Some(from), Some(to)) = (position, platform.position()) {
+                body.set_linear_velocity((to - from) / dt);
+            }
+            if let (Some((axis, from)), Some((_, to))) = (rotation, platform.rotation()) {
+                let mut delta = to - from;
+                // loops wrap around at a full turn
+                let turn = 2.0 * std::f32::consts::PI;
+                if delta > turn * 0.5 {
+                    delta -= turn;
+                } else if delta < -turn * 0.5 {
+                    delta += turn;
+                }
+                body.set_angular_velocity(axis * (delta / dt));
+            }
+        }
+    }
+
+    // Add the forces of the fields each dynamic body is in, for one tick
+    fn apply_force_fields(&mut self) {
+        if self.force_fields.is_empty() {
+            return;
+        }
+        let gravity = Vec3::new(0.0, -GRAVITY, 0.0);
+        for body in self.bodies() {
+            let mut body = body.borrow_mut();
+            if body.is_kinematic() {
+                continue;
+            }
+
+            let position = body.get_center_of_mass_position();
+            let velocity = body.get_linear_velocity();
+            let mut acceleration: Vec3 = na::zero();
+            let mut gravity_replaced = false;
+            for &(_, ref field) in &self.force_fields {
+                if field.mask & body.config.category_bits != 0 {
+                    acceleration = acceleration + field.acceleration(position, velocity);
+                    gravity_replaced = gravity_replaced || field.replaces_gravity(position);
+                }
+            }
+            // only once however many gravity zones overlap here
+            if gravity_replaced {
+                acceleration = acceleration - gravity;
+            }
+
+            if acceleration != na::zero() {
+                let force = acceleration * body.get_mass();
+                body.add_force(force);
+                // resting bodies were disabled and would ignore the force
+                unsafe {
+                    ode::dBodyEnable(body.ode_body);
+                }
+            }
+        }
+    }
+
+    // Float the dynamic bodies in the liquids by the volume they have under the surface, for one
+    // tick. Each geom counts as its bounding box evenly filled with its volume and gets pushed up
+    // at the middle of its part under the surface, so that the parts of a compound body each
+    // float on the waves where they are.
+    fn apply_liquids(&mut self) {
+        if self.liquids.is_empty() {
+            return;
+        }
+        for body in self.bodies() {
+            let body = body.borrow();
+            if body.is_kinematic() || body.geom_volumes.is_empty() {
+                continue;
+            }
+
+            let mut total_volume = 0.0;
+            let mut submerged_volume = 0.0;
+            let mut linear_drag = 0.0;
+            let mut angular_drag = 0.0;
+            for (&geom, &volume) in body.ode_geoms.iter().zip(body.geom_volumes.iter()) {
+                total_volume += volume;
+                let mut aabb = [0.0; 6];
+                unsafe {
+                    ode::dGeomGetAABB(geom, aabb.as_mut_ptr());
+                }
+                let x = ((aabb[0] + aabb[1]) * 0.5) as f32;
+                let z = ((aabb[4] + aabb[5]) * 0.5) as f32;
+
+                for &(_, ref liquid) in &self.liquids {
+                    if !liquid.enabled || liquid.mask & body.config.category_bits == 0 ||
+                       !liquid.covers(x, z) {
+                        continue;
+                    }
+                    let surface = self.liquid_surface_at(liquid, x, z);
+                    let (fraction, y) = liquid.submerged(aabb[2] as f32, aabb[3] as f32, surface);
+                    if fraction == 0.0 {
+                        continue;
+                    }
+
+                    let under = volume * fraction;
+                    submerged_volume += under;
+                    linear_drag += under * liquid.linear_drag;
+                    angular_drag += under * liquid.angular_drag;
+                    let lift = liquid.density * under * GRAVITY;
+                    unsafe {
+                        ode::dBodyAddForceAtPos(body.ode_body,
+                                                0.0,
+                                                lift as f64,
+                                                0.0,
+                                                x as f64,
+                                                y as f64,
+                                                z as f64);
+                    }
+                }
+            }
+            if submerged_volume == 0.0 || total_volume == 0.0 {
+                continue;
+            }
+
+            // each liquid slows the body by how much of it is in there
+            let linear = (1.0 - linear_drag / total_volume * PHYS_DT).max(0.0) as f64;
+            let angular = (1.0 - angular_drag / total_volume * PHYS_DT).max(0.0) as f64;
+            unsafe {
+                let v = ode::dBodyGetLinearVel(body.ode_body);
+                ode::dBodySetLinearVel(body.ode_body,
+                                       *v * linear,
+                                       *v.offset(1) * linear,
+                                       *v.offset(2) * linear);
+                let w = ode::dBodyGetAngularVel(body.ode_body);
+                ode::dBodySetAngularVel(body.ode_body,
+                                        *w * angular,
+                                        *w.offset(1) * angular,
+                                        *w.offset(2) * angular);
+                ode::dBodyEnable(body.ode_body);
+            }
+        }
+    }
+
+    // the body pair key of the event is in handle order
+    fn record_contact(&mut self, b1: &Body, b2: &Body, geom: &ode::dContactGeom) {
+        let mut v1 = [0.0; 4];
+        let mut v2 = [0.0; 4];
+        unsafe {
+            ode::dBodyGetPointVel(b1.ode_body, geom.pos[0], geom.pos[1], geom.pos[2], &mut v1[0]);
+            ode::dBodyGetPointVel(b2.ode_body, geom.pos[0], geom.pos[1], geom.pos[2], &mut v2[0]);
+        }
+        let relative_velocity = Vec3::new((v1[0] - v2[0]) as f32,
+                                          (v1[1] - v2[1]) as f32,
+                                          (v1[2] - v2[2]) as f32);
+        let normal = Vec3::new(geom.normal[0] as f32,
+                               geom.normal[1] as f32,
+                               geom.normal[2] as f32);
+
+        let mut event = ContactEvent {
+            bodies: (b1.handle, b2.handle),
+            point: Vec3::new(geom.pos[0] as f32, geom.pos[1] as f32, geom.pos[2] as f32),
+            normal: normal,
+            normal_velocity: na::dot(&normal, &relative_velocity),
+            phase: ContactPhase::Begin,
+        };
+        if event.bodies.1 < event.bodies.0 {
+            // the relative velocity flips too, so the normal velocity stays
+            event.bodies = (event.bodies.1, event.bodies.0);
+            event.normal = -event.normal;
+        }
+
+        self.new_touching.insert(event.bodies, event);
+    }
+
+    // Compare the pairs found by the collision pass to the previous tick's for the phases
+    fn update_contacts(&mut self) {
+        let mut previous = std::mem::replace(&mut self.touching, HashMap::new());
+        self.tick_contacts.clear();
+
+        for (pair, mut event) in self.new_touching.drain() {
+            event.phase = if previous.remove(&pair).is_some() {
+                ContactPhase::Persist
+            } else {
+                ContactPhase::Begin
+            };
+            self.tick_contacts.push(event.clone());
+            self.touching.insert(pair, event);
+        }
+        for (_, mut event) in previous.drain() {
+            event.phase = ContactPhase::End;
+            self.tick_contacts.push(event);
+        }
+
+        // hash order would make replays play sounds in a different order
+        self.tick_contacts.sort_by_key(|e| e.bodies);
+    }
+
+    // The contact events of the latest tick
+    pub fn tick_contacts(&self) -> &[ContactEvent] {
+        &self.tick_contacts
+    }
+
+    // a sensor sees the bodies of the categories that it would collide with; two sensors don't
+    // see each other
+    fn record_sensor_overlap(&mut self, sensor: &Body, other: &Body) {
+        if sensor.config.sensor && !other.config.sensor &&
+           sensor.config.collide_bits & other.config.category_bits != 0 {
+            self.new_sensor_overlaps.insert((sensor.handle, other.handle));
+        }
+    }
+
+    fn update_sensors(&mut self) {
+        let previous = std::mem::replace(&mut self.sensor_overlaps, HashSet::new());
+        self.tick_sensors.clear();
+
+        for &(sensor, body) in self.new_sensor_overlaps.iter() {
+            let phase = if previous.contains(&(sensor, body)) {
+                SensorPhase::Stay
+            } else {
+                SensorPhase::Enter
+            };
+            self.tick_sensors.push(SensorEvent {
+                sensor: sensor,
+                body: body,
+                phase: phase,
+            });
+        }
+        for &(sensor, body) in previous.difference(&self.new_sensor_overlaps) {
+            self.tick_sensors.push(SensorEvent {
+                sensor: sensor,
+                body: body,
+                phase: SensorPhase::Exit,
+            });
+        }
+
+        self.sensor_overlaps = std::mem::replace(&mut self.new_sensor_overlaps, HashSet::new());
+        self.tick_sensors.sort_by_key(|e| (e.sensor, e.body));
+    }
+
+    // The sensor events of the latest tick
+    pub fn tick_sensors(&self) -> &[SensorEvent] {
+        &self.tick_sensors
+    }
+
+    #[allow(dead_code)]
+    pub fn materials_mut(&mut self) -> &mut MaterialTable {
+        &mut self.materials
+    }
+
+    // Paint a rectangle of heightfield samples, inclusive, with a material
+    #[allow(dead_code)]
+    pub fn set_terrain_material(&mut self, min: (i32, i32), max: (i32, i32), material: Material) {
+        let (width, depth) = self.heightfield_resolution;
+        if self.terrain_materials.is_empty() {
+            let base = self.chunks
+                .first()
+                .map_or(Material::Default, |c| c.body.borrow().config.material);
+            self.terrain_materials = vec![base; (width * depth) as usize];
+        }
+
+        for z in min.1.max(0)..(max.1 + 1).min(depth) {
+            for x in min.0.max(0)..(max.0 + 1).min(width) {
+                self.terrain_materials[(x + z * width) as usize] = material;
+            }
+        }
+    }
+
+    // material of a body at a contact point; only the terrain varies by position
+    fn contact_material(&self, body: &Body, pos: Vec3) -> Material {
+        match *body.shape {
+            BodyShape::HeightField if !self.terrain_materials.is_empty() => {
+                let (width, depth) = self.heightfield_resolution;
+                let (x, z) = self.world_to_grid(pos.x, pos.z);
+                let x = na::clamp(x.round() as i32, 0, width - 1);
+                let z = na::clamp(z.round() as i32, 0, depth - 1);
+                self.terrain_materials[(x + z * width) as usize]
+            }
+            _ => body.config.material,
+        }
+    }
+
+    // Make the ODE geoms of a shape in the world space, with their offsets from the body
+    fn create_geoms(&self,
+                    shape: &BodyShape,
+                    offset: Option<Iso3>,
+                    geoms: &mut Vec<(ode::dGeomID, Option<Iso3>)>,
+                    data: &mut Vec<OdeGeomData>) {
+        let space = self.ode_space;
+        let geom = match *shape {
+            BodyShape::Sphere { radius } => unsafe { ode::dCreateSphere(space, radius as f64) },
+            BodyShape::Box { size } => unsafe {
+                ode::dCreateBox(space, size.x as f64, size.y as f64, size.z as f64)
+            },
+            BodyShape::Capsule { radius, length } => unsafe {
+                ode::dCreateCapsule(space, radius as f64, length as f64)
+            },
+            BodyShape::Cylinder { radius, length } => unsafe {
+                ode::dCreateCylinder(space, radius as f64, length as f64)
+            },
+            BodyShape::TriangleSoup { ref vertices, ref indices } => {
+                unsafe {
+                    let trimesh_data = ode::dGeomTriMeshDataCreate();
+
+                    ode::dGeomTriMeshDataBuildDouble(trimesh_data,
+                                                     vertices.as_ptr() as
+                                                     *const std::os::raw::c_void,
+                                                     8 * 3, // vertex stride
+                                                     vertices.len() as i32 / 3,
+                                                     indices.as_ptr() as
+                                                     *const std::os::raw::c_void,
+                                                     indices.len() as i32,
+                                                     4 * 3);
+
+                    data.push(OdeGeomData::TriMesh(trimesh_data));
+                    ode::dCreateTriMesh(space, trimesh_data, None, None, None)
+                }
+            }
+            BodyShape::ConvexHull { ref planes, ref points, ref polygons } => unsafe {
+                ode::dCreateConvex(space,
+                                   planes.as_ptr(),
+                                   planes.len() as u32 / 4,
+                                   points.as_ptr(),
+                                   points.len() as u32 / 3,
+                                   polygons.as_ptr())
+            },
+            BodyShape::Compound { ref parts } => {
+                for &(ref part_offset, ref part) in parts.iter() {
+                    let part_offset = match offset {
+                        Some(offset) => offset * *part_offset,
+                        None => *part_offset,
+                    };
+                    self.create_geoms(part, Some(part_offset), geoms, data);
+                }
+                return;
+            }
+            BodyShape::HeightField => unreachable!(), // heightfield is special
+        };
+        geoms.push((geom, offset));
+    }
+
+    pub fn add_body(&mut self,
+                    mesh: Rc<RefCell<mesh::Mesh>>,
+                    texture: Rc<texture::Texture>,
+                    shape: Rc<BodyShape>,
+                    config: BodyConfig)
+                    -> Rc<RefCell<Body>> {
+        let body = self.add_headless_body(shape, config);
+        {
+            let mut b = body.borrow_mut();
+            b.mesh = Some(mesh);
+            b.texture = Some(texture);
+        }
+        body
+    }
+
+    // A body with nothing to draw it with, for simulating without a display
+    pub fn add_headless_body(&mut self,
+                             shape: Rc<BodyShape>,
+                             config: BodyConfig)
+                             -> Rc<RefCell<Body>> {
+        let ode_body = unsafe { ode::dBodyCreate(self.ode_world) };
 
-                let normal = Vec3::new((xm - h) + (h - xp), 1.0, (zm - h) + (h - zp));
-                let normal = normal.normalize();
-                gpu_vert.position[1] = heightfield[hi];
-                gpu_vert.normal[0] = normal.x;
-                gpu_vert.normal[1] = normal.y;
-                gpu_vert.normal[2] = normal.z;
+        let mut ode_geoms = Vec::new();
+        let mut ode_data = Vec::new();
+        self.create_geoms(&shape, None, &mut ode_geoms, &mut ode_data);
 
-                let offset = heightfield_velocity[hi];
+        println!("Create body {:?}", config);
+        let handle = self.next_handle();
+        let mut center_of_mass = Vec3::new(0.0, 0.0, 0.0);
+        let mut geom_volumes = Vec::new();
+        unsafe {
+            ode::dBodySetData(ode_body, handle.to_user_data());
+            ode::dBodySetPosition(ode_body, 0.0, 0.0, 0.0);
+            if config.fixed {
+                ode::dBodySetKinematic(ode_body);
+            } else {
+                ode::dBodySetDynamic(ode_body);
+                let mut mass = shape_mass(&shape, config.density, &mut ode_geoms.iter());
+                if mass.mass <= 0.0 {
+                    // e.g. a trimesh that isn't closed
+                    ode::dMassSetSphere(&mut mass, config.density as f64, 1.0);
+                }
+                // weighing each part at unit density gives its volume
+                let mut leaves = Vec::new();
+                leaf_shapes(&shape, &mut leaves);
+                for (i, leaf) in leaves.iter().enumerate() {
+                    let volume = shape_mass(leaf, 1.0, &mut ode_geoms[i..i + 1].iter()).mass;
+                    geom_volumes.push(volume.max(0.0) as f32);
+                }
+                if let Some(total) = config.mass {
+                    ode::dMassAdjust(&mut mass, total as f64);
+                }
+                // ODE wants the body origin at the center of mass, so the geoms move instead
+                center_of_mass = Vec3::new(mass.c[0] as f32, mass.c[1] as f32, mass.c[2] as f32);
+                ode::dMassTranslate(&mut mass, -mass.c[0], -mass.c[1], -mass.c[2]);
+                if let Some(inertia) = config.inertia {
+                    let total = mass.mass;
+                    ode::dMassSetParameters(&mut mass,
+                                            total,
+                                            0.0,
+                                            0.0,
+                                            0.0,
+                                            inertia.x as f64,
+                                            inertia.y as f64,
+                                            inertia.z as f64,
+                                            0.0,
+                                            0.0,
+                                            0.0);
+                }
+                ode::dBodySetMass(ode_body, &mass);
+            }
+            for &(geom, offset) in ode_geoms.iter() {
+                ode::dGeomSetBody(geom, ode_body);
+                // offsets only work for geoms that are already attached
+                if offset.is_some() || center_of_mass != na::zero() {
+                    let offset = Iso3::new(-center_of_mass, na::zero()) *
+                                 offset.unwrap_or(Iso3::new(na::zero(), na::zero()));
+                    let (position, rotation) = iso_to_ode(&offset);
+                    ode::dGeomSetOffsetPosition(geom, position[0], position[1], position[2]);
+                    ode::dGeomSetOffsetRotation(geom, rotation.as_ptr());
+                }
+
+                ode::dGeomSetCategoryBits(geom, config.category_bits);
+                ode::dGeomSetCollideBits(geom, config.collide_bits);
+            }
+        };
+
+        let body = Rc::new(RefCell::new(Body {
+            mesh: None,
+            shape: shape,
+            texture: None,
+            config: config.clone(),
+            ode_body: ode_body,
+            ode_geoms: ode_geoms.iter().map(|&(geom, _)| geom).collect(),
+            ode_data: ode_data,
+            center_of_mass: center_of_mass,
+            handle: handle,
+            collide_sound: config.collide_sound,
+            shaded: false,
+            previous: None,
+            geom_volumes: geom_volumes,
+            world_alive: self.alive.clone(),
+        }));
+        self.insert_body(body.clone());
+        body
+    }
 
-                let offset = offset * 0.2;
+    // Build the physical terrain from a heightmap, in chunks of chunk_size samples per side, or
+    // in one piece if chunk_size is 0. This does not touch the GPU, so the world can be simulated
+    // headless; call setup_heightfield_mesh afterwards to make it visible. The chunks read the
+    // heights through a pointer to the world, so it must not move after this.
+    pub fn setup_heightfield(&mut self, heightmap: &Heightmap, chunk_size: i32) {
+        let reso = heightmap.resolution;
+        self.heightfield = heightmap.heights.clone();
+        self.heightfield_velocity = vec![0.0; heightmap.heights.len()];
+        self.heightfield_origin = heightmap.heights.clone();
+        self.heightfield_resolution = reso;
 
-                gpu_vert.color_tint[0] = offset;
-                gpu_vert.color_tint[1] = offset;
-                gpu_vert.color_tint[2] = offset;
+        let (width, depth) = reso;
+        let step = if chunk_size > 0 { chunk_size } else { width.max(depth) };
+        // the last chunk on a row or column takes the shared edge too
+        let end = |start: i32, len: i32| if start + step >= len - 1 { len } else { start + step };
+
+        self.chunk_columns = (width - 2) / step + 1;
+        let mut z = 0;
+        while z < depth - 1 {
+            let mut x = 0;
+            while x < width - 1 {
+                let size = ((x + step).min(width - 1) - x + 1, (z + step).min(depth - 1) - z + 1);
+                let chunk = self.create_chunk((x, z), size, (end(x, width), end(z, depth)));
+                self.chunks.push(chunk);
+                x += step;
             }
+            z += step;
+        }
+    }
+
+    fn create_chunk(&mut self,
+                    min: (i32, i32),
+                    resolution: (i32, i32),
+                    owned_max: (i32, i32))
+                    -> TerrainChunk {
+        let heightfield_data = unsafe { ode::dGeomHeightfieldDataCreate() };
+        let sampler = Box::new(ChunkSampler {
+            world: self as *const World,
+            min: min,
         });
+
+        let scale = 1.0; // "vertical height scale multiplier"
+        let offset = 0.0f64; // vetical height offset
+        let thickness = 0.1;
+        let wrap = false as i32; // whether to wrap the heightfield infinitely
+
+        unsafe {
+            ode::dGeomHeightfieldDataBuildCallback(heightfield_data,
+                                                   &*sampler as *const _ as *mut _,
+                                                   Some(heightfield_callback),
+                                                   (resolution.0 as f64 - 1.0) *
+                                                   self.heightfield_scale as f64,
+                                                   (resolution.1 as f64 - 1.0) *
+                                                   self.heightfield_scale as f64,
+                                                   resolution.0,
+                                                   resolution.1,
+                                                   scale,
+                                                   offset,
+                                                   thickness,
+                                                   wrap);
+            ode::dGeomHeightfieldDataSetBounds(heightfield_data,
+                                               -5000.0, // min height
+                                               5000.0 /* max height */);
+        };
+
+        let geom =
+            unsafe { ode::dCreateHeightfield(self.ode_space, heightfield_data, true as i32) };
+
+        // the geom is centered at its position
+        let s = self.heightfield_scale;
+        let (cx, cz) = self.grid_to_world(min.0 as f32 + (resolution.0 - 1) as f32 * 0.5,
+                                          min.1 as f32 + (resolution.1 - 1) as f32 * 0.5);
+        let center = Vec3::new(cx, 0.0, cz);
+
+        let body = unsafe {
+            // FIXME: use add_body
+            let ode_body = ode::dBodyCreate(self.ode_world);
+            ode::dGeomSetBody(geom, ode_body);
+            let handle = self.next_handle();
+            ode::dBodySetData(ode_body, handle.to_user_data());
+            ode::dBodySetKinematic(ode_body);
+            ode::dBodySetPosition(ode_body, center.x as f64, center.y as f64, center.z as f64);
+            ode::dGeomSetCategoryBits(geom, BODY_CATEGORY_TERRAIN_BIT);
+            ode::dGeomSetCollideBits(geom, BODY_COLLIDE_TERRAIN);
+            let body = Rc::new(RefCell::new(Body {
+                mesh: None,
+                shape: Rc::new(BodyShape::HeightField),
+                texture: None,
+                config: BodyConfig { material: Material::Grass, ..Default::default() },
+                ode_body: ode_body,
+                ode_geoms: vec![geom],
+                ode_data: vec![OdeGeomData::HeightField(heightfield_data)],
+                center_of_mass: Vec3::new(0.0, 0.0, 0.0),
+                handle: handle,
+                collide_sound: None,
+                shaded: true,
+                previous: None,
+                geom_volumes: Vec::new(),
+                world_alive: self.alive.clone(),
+            }));
+            self.insert_body(body.clone());
+            self.terrain_bodies.insert(handle);
+            body
+        };
+
+        TerrainChunk {
+            min: min,
+            resolution: resolution,
+            owned_max: owned_max,
+            center: center,
+            half_size: ((resolution.0 - 1) as f32 * s * 0.5, (resolution.1 - 1) as f32 * s * 0.5),
+            body: body,
+            mesh: None,
+            mesh_idx: Vec::new(),
+            _sampler: sampler,
+            awake_ticks: CHUNK_WAKE_TICKS,
+            region: WaveRegion::default(),
+            dirty: true,
+        }
+    }
+
+    // Create the render meshes for the terrain set up earlier with setup_heightfield
+    pub fn setup_heightfield_mesh<F: Facade>(&mut self,
+                                             f: &F,
+                                             visible_texture: Rc<texture::Texture>)
+                                             -> Result<()> {
+        assert!(!self.chunks.is_empty(), "heightfield not set up");
+        for chunk in self.chunks.iter_mut() {
+            let (mesh, idx) = Mesh::from_heightfield(f,
+                                                     self.heightfield_resolution,
+                                                     &self.heightfield,
+                                                     self.heightfield_scale,
+                                                     chunk.min,
+                                                     chunk.resolution)
+                .chain_err(|| "failed to create landscape mesh")?;
+            let mesh = Rc::new(RefCell::new(mesh));
+
+            {
+                let mut body = chunk.body.borrow_mut();
+                body.mesh = Some(mesh.clone());
+                body.texture = Some(visible_texture.clone());
+            }
+
+            chunk.mesh_idx = idx;
+            chunk.mesh = Some(mesh);
+        }
+
+        Ok(())
+    }
+
+    // the handle that the next inserted body gets
+    fn next_handle(&self) -> BodyHandle {
+        match self.free_slots.last() {
+            Some(&index) => {
+                BodyHandle {
+                    index: index,
+                    generation: self.bodies[index as usize].generation,
+                }
+            }
+            None => {
+                BodyHandle {
+                    index: self.bodies.len() as u32,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn insert_body(&mut self, body: Rc<RefCell<Body>>) {
+        let handle = body.borrow().handle;
+        if (handle.index as usize) < self.bodies.len() {
+            self.free_slots.pop();
+            self.bodies[handle.index as usize].body = Some(body);
+        } else {
+            self.bodies.push(BodySlot {
+                generation: handle.generation,
+                body: Some(body),
+            });
+        }
+    }
+
+    pub fn body(&self, handle: BodyHandle) -> Option<&Rc<RefCell<Body>>> {
+        self.bodies
+            .get(handle.index as usize)
+            .and_then(|slot| if slot.generation == handle.generation {
+                slot.body.as_ref()
+            } else {
+                None
+            })
+    }
+
+    pub fn del_body(&mut self, handle: BodyHandle) {
+        let body = {
+            let slot = &mut self.bodies[handle.index as usize];
+            assert!(slot.generation == handle.generation, "stale body handle");
+            slot.generation += 1;
+            slot.body.take().unwrap()
+        };
+        self.free_slots.push(handle.index);
+
+        // the ODE objects go away when the last reference to the body is dropped, but someone
+        // else (e.g. a joint) might still hold one. take it out of collisions right away.
+        unsafe {
+            for &geom in body.borrow().ode_geoms.iter() {
+                ode::dSpaceRemove(self.ode_space, geom);
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            bodies: self.bodies().map(|b| b.borrow().snapshot()).collect(),
+            heightfield: self.heightfield.clone(),
+            heightfield_origin: self.heightfield_origin.clone(),
+            heightfield_velocity: self.heightfield_velocity.clone(),
+            wave_sources: self.wave_sources.clone(),
+            platforms: self.platforms.clone(),
+            touching: self.touching.clone(),
+            sensor_overlaps: self.sensor_overlaps.clone(),
+            chunk_awake_ticks: self.chunks.iter().map(|c| c.awake_ticks).collect(),
+            leftover_dt: self.leftover_dt,
+            accum_dt: self.accum_dt,
+        }
+    }
+
+    // Fails without changing anything if the snapshot is of a different terrain
+    #[allow(dead_code)]
+    pub fn restore(&mut self, snap: &Snapshot) -> Result<()> {
+        if snap.heightfield.len() != self.heightfield.len() ||
+           snap.chunk_awake_ticks.len() != self.chunks.len() {
+            bail!("snapshot of a terrain of {} samples in {} chunks, not {} in {}",
+                  snap.heightfield.len(),
+                  snap.chunk_awake_ticks.len(),
+                  self.heightfield.len(),
+                  self.chunks.len());
+        }
+
+        for body_snap in snap.bodies.iter() {
+            if let Some(body) = self.body(body_snap.handle) {
+                body.borrow_mut().restore(body_snap);
+            }
+        }
+
+        self.heightfield.copy_from_slice(&snap.heightfield);
+        self.heightfield_origin.copy_from_slice(&snap.heightfield_origin);
+        self.heightfield_velocity.copy_from_slice(&snap.heightfield_velocity);
+        self.wave_sources = snap.wave_sources.clone();
+        self.platforms = snap.platforms.clone();
+        self.touching = snap.touching.clone();
+        self.sensor_overlaps = snap.sensor_overlaps.clone();
+        for (chunk, &ticks) in self.chunks.iter_mut().zip(snap.chunk_awake_ticks.iter()) {
+            chunk.awake_ticks = ticks;
+            chunk.dirty = true;
+        }
+        self.leftover_dt = snap.leftover_dt;
+        self.accum_dt = snap.accum_dt;
+
+        self.update_landscape_mesh();
+        Ok(())
+    }
+
+    // How far the time is between the last tick and the next one, from 0 to 1; draw the bodies
+    // this far from their previous state to the current one
+    pub fn interpolation_alpha(&self) -> f32 {
+        self.leftover_dt / PHYS_DT
+    }
+
+    // Simulated time in seconds, advances in PHYS_DT ticks
+    pub fn time(&self) -> f32 {
+        self.accum_dt
+    }
+
+    // At least one, or the world would never move
+    pub fn set_max_substeps(&mut self, ticks: u32) {
+        self.max_substeps = ticks.max(1);
+    }
+
+    // Advance the world state forwards by dt seconds, in at most max_substeps ticks. Before each
+    // fixed PHYS_DT tick, tick() gets to apply the input of that tick to the world. Returns the
+    // events of all the ticks taken.
+    pub fn step<F>(&mut self, frame_dt: f32, mut tick: F) -> Result<StepEvents>
+        where F: FnMut(&mut World) -> Result<()>
+    {
+        let mut events = StepEvents::default();
+        self.leftover_dt += frame_dt;
+
+        let mut ticks = 0;
+        while self.leftover_dt >= PHYS_DT {
+            if ticks == self.max_substeps {
+                // simulating all of it would only make the next frame later still
+                self.leftover_dt %= PHYS_DT;
+                break;
+            }
+            ticks += 1;
+            self.leftover_dt -= PHYS_DT;
+            self.accum_dt += PHYS_DT;
+
+            for body in self.bodies() {
+                body.borrow_mut().save_previous();
+            }
+            tick(self)?;
+            self.step_wave_sources(PHYS_DT);
+            self.update_platforms(PHYS_DT);
+            self.apply_force_fields();
+            self.apply_liquids();
+            self.update_chunk_sleep();
+            self.step_waves(PHYS_DT);
+
+            unsafe {
+                ode::dSpaceCollide(self.ode_space,
+                                   self as *mut _ as *mut std::os::raw::c_void,
+                                   Some(near_callback));
+            }
+            self.update_contacts();
+            self.update_sensors();
+            events.contacts.extend(self.tick_contacts.iter().cloned());
+            events.sensors.extend(self.tick_sensors.iter().cloned());
+
+            unsafe {
+                ode::dWorldStep(self.ode_world, PHYS_DT as f64);
+                ode::dJointGroupEmpty(self.ode_contact_group);
+            }
+        }
+
+        self.update_landscape_mesh();
+
+        Ok(events)
+    }
+
+    // deform the meshes of the changed chunks based on heightfield, if there are any to render
+    fn update_landscape_mesh(&mut self) {
+        let &mut World { ref mut chunks,
+                         ref heightfield,
+                         // ref heightfield_origin,
+                         ref heightfield_velocity,
+                         ref heightfield_resolution,
+                         .. } = self;
+
+        for chunk in chunks.iter_mut().filter(|c| c.dirty) {
+            chunk.dirty = false;
+            let heightfield_idx = &chunk.mesh_idx;
+            let mut mesh = match chunk.mesh {
+                Some(ref mesh) => mesh.borrow_mut(),
+                None => continue,
+            };
+
+            mesh.update_mesh(|orig_verts, new_verts| {
+                for (index, (_orig_vert, gpu_vert)) in orig_verts.iter()
+                    .zip(new_verts.iter_mut())
+                    .enumerate() {
+
+                    use na::Norm;
+                    let hi = heightfield_idx[index];
+                    // let offset = heightfield[hi] - heightfield_origin[hi];
+                    // let velo = heightfield_velocity[hi];
+
+                    // if offset.abs() < 0.01 {
+                    //    *gpu_vert = *orig_vert;
+                    //    continue;
+                    // }
+
+                    let xi = heightfield_idx[index] % heightfield_resolution.0 as usize;
+                    let zi = heightfield_idx[index] / heightfield_resolution.0 as usize;
+                    let h = heightfield[hi];
+
+                    let xm = if xi > 0 { heightfield[hi - 1] } else { 0.0 };
+                    let xp = if xi + 1 < heightfield_resolution.0 as usize {
+                        heightfield[hi + 1]
+                    } else {
+                        0.0
+                    };
+                    let zm = if zi > 0 {
+                        heightfield[hi - heightfield_resolution.0 as usize]
+                    } else {
+                        0.0
+                    };
+                    let zp = if zi + 1 < heightfield_resolution.1 as usize {
+                        heightfield[hi + heightfield_resolution.0 as usize]
+                    } else {
+                        0.0
+                    };
+
+                    let normal = Vec3::new((xm - h) + (h - xp), 1.0, (zm - h) + (h - zp));
+                    let normal = normal.normalize();
+                    gpu_vert.position[1] = heightfield[hi];
+                    gpu_vert.normal[0] = normal.x;
+                    gpu_vert.normal[1] = normal.y;
+                    gpu_vert.normal[2] = normal.z;
+
+                    let offset = heightfield_velocity[hi];
+
+                    let offset = offset * 0.2;
+
+                    gpu_vert.color_tint[0] = offset;
+                    gpu_vert.color_tint[1] = offset;
+                    gpu_vert.color_tint[2] = offset;
+                }
+            });
+        }
+    }
+
+    // Everything that a geom outside the space touches, limited to bodies in the mask categories
+    fn query(&self, geom: ode::dGeomID, mask: u64) -> Vec<(BodyHandle, ode::dContactGeom)> {
+        let mut query = QueryState {
+            geom: geom,
+            mask: mask,
+            contacts: Vec::new(),
+        };
+        unsafe {
+            ode::dGeomSetCategoryBits(geom, 0);
+            ode::dGeomSetCollideBits(geom, mask);
+            ode::dSpaceCollide2(geom,
+                                self.ode_space as ode::dGeomID,
+                                &mut query as *mut _ as *mut std::os::raw::c_void,
+                                Some(query_callback));
+        }
+        query.contacts
+    }
+
+    // Bodies hit by a ray, nearest first
+    pub fn raycast(&self,
+                   origin: Vec3,
+                   direction: Vec3,
+                   max_distance: f32,
+                   mask: u64)
+                   -> Vec<QueryHit> {
+        if direction.norm() == 0.0 {
+            return Vec::new();
+        }
+        let direction = direction.normalize();
+
+        let contacts = unsafe {
+            let ray = ode::dCreateRay(std::ptr::null_mut(), max_distance as f64);
+            ode::dGeomRaySet(ray,
+                             origin.x as f64,
+                             origin.y as f64,
+                             origin.z as f64,
+                             direction.x as f64,
+                             direction.y as f64,
+                             direction.z as f64);
+            ode::dGeomRaySetClosestHit(ray, 1);
+            let contacts = self.query(ray, mask);
+            ode::dGeomDestroy(ray);
+            contacts
+        };
+
+        // the depth of a ray contact is its distance from the ray start
+        closest_hits(contacts.iter()
+            .map(|&(body, ref c)| QueryHit::from_contact(body, c, c.depth as f32))
+            .collect())
+    }
+
+    // Bodies that a sphere swept along a ray would hit, nearest first. The sweep is done in
+    // steps of half the radius, but in no more than SPHERE_CAST_MAX_STEPS of them, so bodies
+    // thinner than a step can be missed; a sphere of no size is a ray.
+    #[allow(dead_code)]
+    pub fn sphere_cast(&self,
+                       origin: Vec3,
+                       direction: Vec3,
+                       radius: f32,
+                       max_distance: f32,
+                       mask: u64)
+                       -> Vec<QueryHit> {
+        if direction.norm() == 0.0 {
+            return Vec::new();
+        }
+        if radius <= 0.0 {
+            return self.raycast(origin, direction, max_distance, mask);
+        }
+        let direction = direction.normalize();
+        // counted rather than added up, so that the end is reached even when a step is lost
+        // in the rounding of a long distance
+        let steps = (max_distance / (radius * 0.5)).ceil().max(1.0);
+        let steps = steps.min(SPHERE_CAST_MAX_STEPS as f32) as u32;
+
+        let sphere = unsafe { ode::dCreateSphere(std::ptr::null_mut(), radius as f64) };
+        let move_to = |t: f32| {
+            let p = origin + direction * t;
+            unsafe { ode::dGeomSetPosition(sphere, p.x as f64, p.y as f64, p.z as f64) };
+        };
+
+        let mut hits = Vec::new();
+        let mut found = HashSet::new();
+        let mut prev_t = 0.0;
+        for i in 0..steps + 1 {
+            let t = max_distance * i as f32 / steps as f32;
+            move_to(t);
+            for (body, contact) in self.query(sphere, mask) {
+                if !found.insert(body) {
+                    continue;
+                }
+                if i == 0 {
+                    hits.push(QueryHit::from_contact(body, &contact, 0.0));
+                    continue;
+                }
+
+                // narrow down where the sphere first touches this body
+                let geoms = self.body(body).unwrap().borrow().ode_geoms.clone();
+                let (mut lo, mut hi) = (prev_t, t);
+                let mut hit_contact = contact;
+                for _ in 0..16 {
+                    let mid = (lo + hi) * 0.5;
+                    move_to(mid);
+                    let mut c: ode::dContactGeom = unsafe { std::mem::zeroed() };
+                    let numc = geoms.iter().map(|&geom| unsafe {
+                        ode::dCollide(sphere,
+                                      geom,
+                                      1,
+                                      &mut c,
+                                      std::mem::size_of::<ode::dContactGeom>() as i32)
+                    }).find(|&n| n > 0).unwrap_or(0);
+                    if numc > 0 {
+                        hi = mid;
+                        hit_contact = c;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                hits.push(QueryHit::from_contact(body, &hit_contact, hi));
+                move_to(t);
+            }
+            prev_t = t;
+        }
+
+        unsafe { ode::dGeomDestroy(sphere) };
+
+        closest_hits(hits)
+    }
+
+    // Bodies within radius from center, nearest first
+    #[allow(dead_code)]
+    pub fn overlap_sphere(&self, center: Vec3, radius: f32, mask: u64) -> Vec<QueryHit> {
+        let contacts = unsafe {
+            let sphere = ode::dCreateSphere(std::ptr::null_mut(), radius as f64);
+            ode::dGeomSetPosition(sphere, center.x as f64, center.y as f64, center.z as f64);
+            let contacts = self.query(sphere, mask);
+            ode::dGeomDestroy(sphere);
+            contacts
+        };
+
+        closest_hits(contacts.iter()
+            .map(|&(body, ref c)| QueryHit::from_contact(body, c, radius - c.depth as f32))
+            .collect())
+    }
+
+    pub fn bodies<'a>(&'a self) -> Box<Iterator<Item = &'a Rc<RefCell<Body>>> + 'a> {
+        Box::new(self.bodies.iter().filter_map(|slot| slot.body.as_ref()))
+    }
+}
+
+impl Drop for World {
+    fn drop(&mut self) {
+        self.chunks.clear();
+        self.terrain_bodies.clear();
+
+        // the geoms go first while their space is still there, also those of the bodies that
+        // someone else holds on to; the world takes the rest of the ODE objects with it
+        for slot in self.bodies.drain(..) {
+            if let Some(body) = slot.body {
+                body.borrow_mut().destroy_ode();
+            }
+        }
+        self.alive.set(false);
+
+        unsafe {
+            ode::dJointGroupDestroy(self.ode_contact_group);
+            ode::dSpaceDestroy(self.ode_space);
+            ode::dWorldDestroy(self.ode_world);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use body::{BODY_CATEGORY_PLAYER_BIT, BODY_CATEGORY_OBJS_BIT, BODY_COLLIDE_PLAYER};
+    use forcefield::{FieldVolume, FieldKind};
+
+    // ODE wants to be set up on every thread that uses it, and the tests run on several
+    pub fn init_ode() {
+        unsafe {
+            ode::dInitODE2(0);
+            ode::dAllocateODEDataForThread(ode::dAllocateODEDataFlags::dAllocateMaskAll as u32);
+        }
+    }
+
+    // Flat ground at height zero, 64 world units wide and in 4x4 chunks. Boxed, because the
+    // chunks point back to the world.
+    pub fn flat_world() -> Box<World> {
+        init_ode();
+        let mut world = Box::new(World::new(2.0));
+        world.setup_heightfield(&Heightmap {
+                                    resolution: (33, 33),
+                                    heights: vec![0.0; 33 * 33],
+                                },
+                                8);
+        world
+    }
+
+    // Like the player ball
+    pub fn add_ball(world: &mut World, position: Vec3) -> Rc<RefCell<Body>> {
+        let ball = world.add_headless_body(Rc::new(BodyShape::Sphere { radius: 1.0 }),
+                                           BodyConfig {
+                                               density: 0.1,
+                                               category_bits: BODY_CATEGORY_PLAYER_BIT,
+                                               collide_bits: BODY_COLLIDE_PLAYER,
+                                               ..Default::default()
+                                           });
+        ball.borrow_mut().set_position(position);
+        ball
+    }
+
+    fn run(world: &mut World, ticks: usize) -> Snapshot {
+        for _ in 0..ticks {
+            world.step(PHYS_DT, |_| Ok(())).unwrap();
+        }
+        world.snapshot()
+    }
+
+    #[test]
+    fn restore_replays_the_same_steps() {
+        let mut world = flat_world();
+        let ball = add_ball(&mut world, Vec3::new(0.0, 3.0, 0.0));
+        ball.borrow_mut().set_linear_velocity(Vec3::new(4.0, 0.0, 1.0));
+        world.impulse(Vec3::new(6.0, 0.0, 2.0), 1.0);
+
+        let start = world.snapshot();
+        let first = run(&mut world, 200);
+        world.restore(&start).unwrap();
+        let second = run(&mut world, 200);
+
+        assert_eq!(first.bodies.len(), second.bodies.len());
+        for (a, b) in first.bodies.iter().zip(second.bodies.iter()) {
+            assert_eq!(a.handle, b.handle);
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.quaternion, b.quaternion);
+            assert_eq!(a.linear_velocity, b.linear_velocity);
+            assert_eq!(a.angular_velocity, b.angular_velocity);
+        }
+        assert!(first.heightfield == second.heightfield);
+        assert!(first.heightfield_velocity == second.heightfield_velocity);
+        assert_eq!(first.accum_dt, second.accum_dt);
+        // and the ball did move in between
+        let handle = ball.borrow().handle;
+        let position = |snap: &Snapshot| {
+            snap.bodies.iter().find(|b| b.handle == handle).unwrap().position
+        };
+        assert!(position(&first) != position(&start));
+    }
+
+    #[test]
+    fn hitch_is_cut_short_and_drawn_between_ticks() {
+        let mut world = flat_world();
+        let ball = add_ball(&mut world, Vec3::new(0.0, 20.0, 0.0));
+        world.set_max_substeps(0);
+        world.step(PHYS_DT, |_| Ok(())).unwrap();
+        assert_eq!(world.time(), PHYS_DT);
+
+        // ten and a half ticks' worth, of which only three are taken and the half is kept
+        world.set_max_substeps(3);
+        let mut ticks = 0;
+        world.step(10.5 * PHYS_DT, |_| {
+                ticks += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(ticks, 3);
+        assert!((world.time() - 4.0 * PHYS_DT).abs() < 1.0e-6);
+        assert!((world.interpolation_alpha() - 0.5).abs() < 1.0e-3);
+
+        let ball = ball.borrow();
+        let translation = |m: na::Matrix4<f32>| Vec3::new(m.m14, m.m24, m.m34);
+        let previous = ball.previous.unwrap().0;
+        let current = ball.get_position();
+        let near = |a: Vec3, b: Vec3| (a - b).norm() < 1.0e-5;
+        assert!(previous.y > current.y);
+        assert!(near(translation(ball.get_interpolated_posrot_homogeneous(0.0)), previous));
+        assert!(near(translation(ball.get_interpolated_posrot_homogeneous(1.0)), current));
+        assert!(near(ball.get_interpolated_position(0.5), (previous + current) * 0.5));
+    }
+
+    // of the 33x33 samples of the flat world, where 16, 16 is the middle
+    fn sample(x: i32, z: i32) -> usize {
+        (x + z * 33) as usize
+    }
+
+    #[test]
+    fn raise_and_lower_move_the_middle_and_not_the_edge() {
+        let mut world = flat_world();
+        // a wave going on in the middle
+        world.heightfield[sample(16, 16)] = 0.5;
+        // three samples to the edge of the brush
+        world.deform_terrain(Vec3::new(0.0, 0.0, 0.0), 6.0, TerrainBrush::Raise(2.0));
+        assert_eq!(world.heightfield_origin[sample(16, 16)], 2.0);
+        assert_eq!(world.heightfield[sample(16, 16)], 2.5);
+        assert!(world.heightfield_origin[sample(17, 16)] > 0.0);
+        assert_eq!(world.heightfield_origin[sample(19, 16)], 0.0);
+        assert_eq!(world.heightfield_origin[sample(16, 13)], 0.0);
+
+        world.deform_terrain(Vec3::new(0.0, 0.0, 0.0), 6.0, TerrainBrush::Lower(2.0));
+        assert_eq!(world.heightfield_origin[sample(16, 16)], 0.0);
+        assert_eq!(world.heightfield[sample(16, 16)], 0.5);
+        assert!(world.heightfield_origin.iter().all(|h| h.abs() < 1.0e-6));
     }
-    pub fn bodies<'a>(&'a self) -> &'a Vec<Rc<RefCell<Body>>> {
-        &self.bodies
+
+    #[test]
+    fn flatten_gets_to_its_height() {
+        let mut world = flat_world();
+        let middle = Vec3::new(0.0, 0.0, 0.0);
+        world.deform_terrain(middle, 6.0, TerrainBrush::Raise(2.0));
+        let flatten = TerrainBrush::Flatten {
+            height: 1.0,
+            strength: 0.5,
+        };
+        for _ in 0..30 {
+            world.deform_terrain(middle, 6.0, flatten);
+        }
+        assert!((world.heightfield_origin[sample(16, 16)] - 1.0).abs() < 1.0e-5);
+        assert_eq!(world.heightfield_origin[sample(19, 16)], 0.0);
+        // the surface went along
+        for (h, o) in world.heightfield.iter().zip(world.heightfield_origin.iter()) {
+            assert!((h - o).abs() < 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn smooth_at_the_edges_reads_the_edge_again() {
+        let mut world = flat_world();
+        for &i in &[sample(0, 0), sample(32, 16), sample(32, 32)] {
+            world.heightfield_origin[i] = 4.0;
+            world.heightfield[i] = 4.0;
+        }
+        for &(x, z) in &[(0, 0), (32, 16), (32, 32)] {
+            let (wx, wz) = world.grid_to_world(x as f32, z as f32);
+            world.deform_terrain(Vec3::new(wx, 0.0, wz), 2.0, TerrainBrush::Smooth(1.0));
+        }
+        // two of the four neighbours are the corner itself
+        assert_eq!(world.heightfield_origin[sample(0, 0)], 2.0);
+        assert_eq!(world.heightfield_origin[sample(32, 32)], 2.0);
+        // and one at the edge
+        assert_eq!(world.heightfield_origin[sample(32, 16)], 1.0);
+        assert_eq!(world.heightfield, world.heightfield_origin);
+    }
+
+    #[test]
+    fn wave_source_moves_the_ground_until_it_runs_out() {
+        let mut world = flat_world();
+        world.add_wave_source(Vec3::new(0.0, 0.0, 0.0), 0.5, 2.0, 8.0, Some(0.5));
+        run(&mut world, 20);
+        assert!(world.heightfield.iter().any(|&h| h != 0.0));
+        run(&mut world, 40);
+        assert!(world.wave_sources.is_empty());
+    }
+
+    fn add_wall(world: &mut World, position: Vec3, size: Vec3) -> Rc<RefCell<Body>> {
+        let wall = world.add_headless_body(Rc::new(BodyShape::Box { size: size }),
+                                           BodyConfig { fixed: true, ..Default::default() });
+        wall.borrow_mut().set_position(position);
+        wall
+    }
+
+    #[test]
+    fn sphere_cast_finds_thin_walls_far_away() {
+        let mut world = flat_world();
+        // much thinner than the sphere, and further than 128 radii away
+        let wall = add_wall(&mut world, Vec3::new(20.0, 2.0, 0.0), Vec3::new(0.02, 4.0, 4.0));
+        let hits = world.sphere_cast(Vec3::new(0.0, 2.0, 0.0),
+                                     Vec3::new(1.0, 0.0, 0.0),
+                                     0.05,
+                                     30.0,
+                                     BODY_CATEGORY_OBJS_BIT);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].body, wall.borrow().handle);
+        assert!((hits[0].distance - 19.94).abs() < 0.01, "hit at {}", hits[0].distance);
+    }
+
+    #[test]
+    fn tiny_sphere_cast_reaches_far() {
+        let mut world = flat_world();
+        let wall = add_wall(&mut world, Vec3::new(1500.0, 2.0, 0.0), Vec3::new(20.0, 4.0, 4.0));
+        let hits = world.sphere_cast(Vec3::new(0.0, 2.0, 0.0),
+                                     Vec3::new(1.0, 0.0, 0.0),
+                                     1.0e-4,
+                                     2000.0,
+                                     BODY_CATEGORY_OBJS_BIT);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].body, wall.borrow().handle);
+        assert!((hits[0].distance - 1490.0).abs() < 0.01, "hit at {}", hits[0].distance);
+    }
+
+    #[test]
+    fn overlap_sphere_reaches_as_far_as_its_radius() {
+        let mut world = flat_world();
+        let wall = add_wall(&mut world, Vec3::new(5.0, 2.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let center = Vec3::new(5.0, 2.0, 3.0);
+        let hits = world.overlap_sphere(center, 3.0, BODY_CATEGORY_OBJS_BIT);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].body, wall.borrow().handle);
+        assert!(world.overlap_sphere(center, 2.0, BODY_CATEGORY_OBJS_BIT).is_empty());
+    }
+
+    #[test]
+    fn overlapping_gravity_zones_add_up() {
+        let mut world = flat_world();
+        let position = Vec3::new(0.0, 20.0, 0.0);
+        let ball = add_ball(&mut world, position);
+        let zone = ForceField::new(FieldVolume::Sphere {
+                                       center: position,
+                                       radius: 5.0,
+                                   },
+                                   FieldKind::Gravity {
+                                       acceleration: Vec3::new(0.0, -0.25 * GRAVITY, 0.0),
+                                   });
+        world.add_force_field(zone.clone());
+        world.add_force_field(zone);
+        run(&mut world, 10);
+        // half the world gravity for 0.1 seconds, give or take the damping
+        let velocity = ball.borrow_mut().get_linear_velocity();
+        assert!((velocity.y + 0.05 * GRAVITY).abs() < 0.01, "falling at {}", velocity.y);
+    }
+
+    #[test]
+    fn wind_pulls_towards_its_velocity() {
+        let mut world = flat_world();
+        world.add_force_field(ForceField::new(FieldVolume::Box {
+                                                  min: Vec3::new(-30.0, 0.0, -30.0),
+                                                  max: Vec3::new(30.0, 30.0, 30.0),
+                                              },
+                                              FieldKind::Wind {
+                                                  velocity: Vec3::new(5.0, 0.0, 0.0),
+                                                  drag: 2.0,
+                                              }));
+        let ball = add_ball(&mut world, Vec3::new(0.0, 20.0, 0.0));
+        run(&mut world, 100);
+        let velocity = ball.borrow_mut().get_linear_velocity();
+        let expected = 5.0 * (1.0 - (-2.0f32).exp());
+        assert!((velocity.x - expected).abs() < 0.1, "{} of {}", velocity.x, expected);
+        assert!(velocity.z.abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn attractor_pulls_in_and_pushes_out_when_negative() {
+        let mut world = flat_world();
+        for &(x, strength) in &[(-10.0, 20.0), (10.0, -20.0)] {
+            world.add_force_field(ForceField::new(FieldVolume::Sphere {
+                                                      center: Vec3::new(x, 20.0, 0.0),
+                                                      radius: 8.0,
+                                                  },
+                                                  FieldKind::Attractor { strength: strength }));
+        }
+        // both end up moving towards negative x
+        let pulled = add_ball(&mut world, Vec3::new(-5.0, 20.0, 0.0));
+        let pushed = add_ball(&mut world, Vec3::new(5.0, 20.0, 0.0));
+        run(&mut world, 10);
+        for ball in &[&pulled, &pushed] {
+            let velocity = ball.borrow_mut().get_linear_velocity();
+            assert!((velocity.x + 2.0).abs() < 0.1, "moving at {}", velocity.x);
+        }
+    }
+
+    #[test]
+    fn vortex_turns_counterclockwise_looking_down_its_axis() {
+        let mut world = flat_world();
+        let center = Vec3::new(0.0, 20.0, 0.0);
+        world.add_force_field(ForceField::new(FieldVolume::Sphere {
+                                                  center: center,
+                                                  radius: 10.0,
+                                              },
+                                              FieldKind::Vortex {
+                                                  axis: Vec3::new(0.0, 1.0, 0.0),
+                                                  strength: 10.0,
+                                                  pull: 5.0,
+                                              }));
+        let ball = add_ball(&mut world, center + Vec3::new(5.0, 0.0, 0.0));
+        run(&mut world, 10);
+        // seen from above, x to the right is z down, so counterclockwise at +x is towards -z
+        let velocity = ball.borrow_mut().get_linear_velocity();
+        assert!((velocity.z + 1.0).abs() < 0.05, "around at {}", velocity.z);
+        assert!((velocity.x + 0.5).abs() < 0.05, "in at {}", velocity.x);
+    }
+
+    #[test]
+    fn masked_out_and_disabled_fields_do_nothing() {
+        let mut world = flat_world();
+        let weightless = |x: f32| {
+            ForceField::new(FieldVolume::Sphere {
+                                center: Vec3::new(x, 20.0, 0.0),
+                                radius: 5.0,
+                            },
+                            FieldKind::Gravity { acceleration: na::zero() })
+        };
+        let mut masked = weightless(-10.0);
+        masked.mask = BODY_CATEGORY_OBJS_BIT;
+        world.add_force_field(masked);
+        let mut disabled = weightless(10.0);
+        disabled.enabled = false;
+        world.add_force_field(disabled);
+        let floating = weightless(0.0);
+        world.add_force_field(floating);
+
+        let balls = [-10.0, 10.0, 0.0]
+            .iter()
+            .map(|&x| add_ball(&mut world, Vec3::new(x, 20.0, 0.0)))
+            .collect::<Vec<_>>();
+        run(&mut world, 10);
+        let fall = |ball: &Rc<RefCell<Body>>| ball.borrow_mut().get_linear_velocity().y;
+        for ball in &balls[..2] {
+            assert!((fall(ball) + 0.1 * GRAVITY).abs() < 0.02, "falling at {}", fall(ball));
+        }
+        assert!(fall(&balls[2]).abs() < 1.0e-3);
+    }
+
+    // Over the whole flat world, going down to any depth
+    fn pool(surface: LiquidSurface, density: f32) -> Liquid {
+        let mut liquid = Liquid::new(Vec2::new(-30.0, -30.0),
+                                     Vec2::new(30.0, 30.0),
+                                     std::f32::NEG_INFINITY,
+                                     surface);
+        liquid.density = density;
+        liquid.linear_drag = 2.0;
+        liquid
+    }
+
+    fn angular_velocity(body: &Body) -> Vec3 {
+        unsafe {
+            let w = ode::dBodyGetAngularVel(body.ode_body);
+            Vec3::new(*w as f32, *w.offset(1) as f32, *w.offset(2) as f32)
+        }
+    }
+
+    #[test]
+    fn lighter_body_floats_at_its_density() {
+        let mut world = flat_world();
+        world.add_liquid(pool(LiquidSurface::Level(5.0), 0.2));
+        let ball = add_ball(&mut world, Vec3::new(0.0, 6.0, 0.0));
+        run(&mut world, 1000);
+        // half as dense as the liquid, so half of it is under
+        let y = ball.borrow().get_position().y;
+        assert!((y - 5.0).abs() < 0.05, "floating at {}", y);
+    }
+
+    #[test]
+    fn denser_body_sinks() {
+        let mut world = flat_world();
+        world.add_liquid(pool(LiquidSurface::Level(20.0), 0.05));
+        let ball = add_ball(&mut world, Vec3::new(0.0, 10.0, 0.0));
+        run(&mut world, 100);
+        let y = ball.borrow().get_position().y;
+        assert!(y < 9.0, "still at {}", y);
+        assert!(ball.borrow_mut().get_linear_velocity().y < 0.0);
+    }
+
+    #[test]
+    fn liquid_drag_slows_moving_and_spinning() {
+        let mut world = flat_world();
+        // as dense as the ball, so that it stays under; only over the negative x half
+        let mut water = Liquid::new(Vec2::new(-30.0, -30.0),
+                                    Vec2::new(0.0, 30.0),
+                                    std::f32::NEG_INFINITY,
+                                    LiquidSurface::Level(20.0));
+        water.density = 0.1;
+        world.add_liquid(water);
+        let wet = add_ball(&mut world, Vec3::new(-15.0, 10.0, 0.0));
+        let dry = add_ball(&mut world, Vec3::new(15.0, 10.0, 0.0));
+        for ball in &[&wet, &dry] {
+            let mut ball = ball.borrow_mut();
+            ball.set_linear_velocity(Vec3::new(5.0, 0.0, 0.0));
+            ball.set_angular_velocity(Vec3::new(0.0, 0.0, 5.0));
+        }
+        run(&mut world, 50);
+
+        // a fraction of 1.0 per second each tick, on top of what the world damps both with
+        let expected = (1.0 - PHYS_DT).powi(50);
+        let linear = wet.borrow_mut().get_linear_velocity().x /
+                     dry.borrow_mut().get_linear_velocity().x;
+        let angular = angular_velocity(&wet.borrow()).z / angular_velocity(&dry.borrow()).z;
+        assert!((linear - expected).abs() < 0.02, "linear {} of {}", linear, expected);
+        assert!((angular - expected).abs() < 0.02, "angular {} of {}", angular, expected);
+    }
+
+    #[test]
+    fn terrain_surface_follows_the_ground() {
+        init_ode();
+        let mut world = Box::new(World::new(2.0));
+        // a slope up along x, 4 high in the middle
+        let heights = (0..33 * 33).map(|i| (i % 33) as f32 * 0.25).collect::<Vec<_>>();
+        world.setup_heightfield(&Heightmap {
+                                    resolution: (33, 33),
+                                    heights: heights,
+                                },
+                                8);
+        world.add_liquid(pool(LiquidSurface::Terrain { depth: 3.0 }, 0.2));
+        let ball = add_ball(&mut world, Vec3::new(0.0, 8.0, 0.0));
+        run(&mut world, 1000);
+
+        let surface = world.height_at(0.0, 0.0) + 3.0;
+        assert_eq!(surface, 7.0);
+        let y = ball.borrow().get_position().y;
+        assert!((y - surface).abs() < 0.05, "floating at {} under {}", y, surface);
+    }
+
+    #[test]
+    fn bodies_and_joints_can_outlive_the_world() {
+        use joint::{Joint, JointKind};
+        let mut world = flat_world();
+        let ball = add_ball(&mut world, Vec3::new(0.0, 3.0, 0.0));
+        let joint = Joint::new(world.ode_world(), JointKind::Ball, ball.clone(), None);
+        let deleted = add_ball(&mut world, Vec3::new(4.0, 3.0, 0.0));
+        let deleted_handle = deleted.borrow().handle;
+        world.del_body(deleted_handle);
+        run(&mut world, 10);
+
+        drop(world);
+        assert!(ball.borrow().ode_body.is_null());
+        drop(joint);
+        drop(ball);
+        drop(deleted);
+    }
+
+    #[test]
+    fn restore_rejects_another_terrain() {
+        let world = flat_world();
+        let mut other = Box::new(World::new(2.0));
+        other.setup_heightfield(&Heightmap {
+                                    resolution: (17, 17),
+                                    heights: vec![0.0; 17 * 17],
+                                },
+                                0);
+        assert!(other.restore(&world.snapshot()).is_err());
     }
 }